@@ -0,0 +1,137 @@
+//! Compares the [`vortex::wire`] fast path with going through `#[serde(flatten)]`,
+//! which is how messages were encoded and decoded before
+//!
+//! `cargo bench --bench wire`
+
+use std::borrow::Cow;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use serde::{Deserialize, Serialize};
+use vortex::{wire::Encoder, Message, NodeId};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum Payload<'a> {
+    Echo {
+        #[serde(borrow)]
+        echo: Cow<'a, str>,
+    },
+    Gossip {
+        gossip_id: u32,
+        values: Vec<u32>,
+    },
+}
+
+#[derive(Serialize)]
+struct FlattenResponse<P> {
+    src: NodeId,
+    dest: NodeId,
+    body: FlattenResponseBody<P>,
+}
+
+#[derive(Serialize)]
+struct FlattenResponseBody<P> {
+    msg_id: Option<u32>,
+    in_reply_to: Option<u32>,
+    #[serde(flatten)]
+    payload: P,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct FlattenMessage<P> {
+    src: NodeId,
+    dest: NodeId,
+    body: FlattenMessageBody<P>,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct FlattenMessageBody<P> {
+    msg_id: Option<u32>,
+    in_reply_to: Option<u32>,
+    #[serde(flatten)]
+    payload: P,
+}
+
+const ECHO: &str =
+    r#"{"id":4,"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"Please echo 35"}}"#;
+const GOSSIP: &str = r#"{"src":"n3","dest":"n1","body":{"type":"gossip","msg_id":812,"gossip_id":77,"values":[1,5,9,12,17,23,31,40,52,61]}}"#;
+
+fn encode(c: &mut Criterion) {
+    let (n1, n3) = (NodeId::node(1), NodeId::node(3));
+    let payloads = [
+        (
+            "echo",
+            Payload::Echo {
+                echo: "Please echo 35".into(),
+            },
+        ),
+        (
+            "gossip",
+            Payload::Gossip {
+                gossip_id: 77,
+                values: vec![1, 5, 9, 12, 17, 23, 31, 40, 52, 61],
+            },
+        ),
+    ];
+
+    for (name, payload) in &payloads {
+        let mut group = c.benchmark_group(format!("encode/{name}"));
+
+        group.bench_function("flatten", |b| {
+            b.iter(|| {
+                serde_json::to_vec(&FlattenResponse {
+                    src: n1,
+                    dest: n3,
+                    body: FlattenResponseBody {
+                        msg_id: Some(812),
+                        in_reply_to: None,
+                        payload: black_box(payload),
+                    },
+                })
+                .unwrap()
+            })
+        });
+
+        let mut encoder = Encoder::new();
+        let mut out = Vec::new();
+        group.bench_function("encoder", |b| {
+            b.iter(|| {
+                out.clear();
+                encoder
+                    .encode(&mut out, n1, n3, Some(812), None, black_box(payload))
+                    .unwrap();
+            })
+        });
+
+        group.finish();
+    }
+}
+
+fn decode(c: &mut Criterion) {
+    for (name, frame) in [("echo", ECHO), ("gossip", GOSSIP)] {
+        let mut group = c.benchmark_group(format!("decode/{name}"));
+
+        group.bench_function("flatten", |b| {
+            b.iter(|| {
+                serde_json::from_str::<FlattenMessage<Payload>>(black_box(frame)).unwrap();
+            })
+        });
+
+        group.bench_function("message", |b| {
+            b.iter(|| {
+                serde_json::from_str::<Message<Payload>>(black_box(frame)).unwrap();
+            })
+        });
+
+        group.bench_function("header", |b| {
+            b.iter(|| vortex::wire::header(black_box(frame.as_bytes())).unwrap())
+        });
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, encode, decode);
+criterion_main!(benches);
@@ -0,0 +1,370 @@
+//! An async client, enabled with the `async` feature
+//!
+//! The [`AsyncClient`] can be cloned and shared between tasks, every clone writes
+//! to the same stdout in order. Messages for the node are read from [`Incoming`],
+//! while replies to [`AsyncClient::call`] go straight to the task waiting for them.
+//!
+//! There are no ticks in async mode: tasks keep their own timers with [`interval`],
+//! so [`Interceptor::on_tick`](crate::interceptor::Interceptor::on_tick) is never
+//! called. Calls time out on their own, and frames that interceptors hold back are
+//! polled every [`POLL_INTERVAL`] so that they are written even when the node is idle.
+//!
+//! ```ignore
+//! let (client, mut incoming) = AsyncClient::new()?;
+//!
+//! while let Some(message) = incoming.next::<Payload>().await? {
+//!     let client = client.clone();
+//!     tokio::spawn(async move {
+//!         let reply = client.call(NodeId::seq_kv(), &Read::<_, u32>::new("key"), TIMEOUT).await;
+//!         // ...
+//!     });
+//! }
+//! ```
+
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    sync::{
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    rpc::{self, Failure, Rpc, Typed},
+    Cluster, Error, MaelstromClient, Message, NodeId, Response,
+};
+
+pub use tokio::time::{interval, sleep, timeout};
+
+type Body = serde_json::Map<String, serde_json::Value>;
+
+/// How often an idle [`AsyncClient`] writes the frames its interceptors released
+pub const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Clone)]
+pub struct AsyncClient {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    node_id: NodeId,
+    node_ids: Vec<NodeId>,
+    cluster: Cluster,
+    client: Mutex<MaelstromClient>,
+    /// Calls waiting for their reply, by the `msg_id` of the request
+    pending: Mutex<HashMap<u32, oneshot::Sender<Body>>>,
+}
+
+/// The messages for the node, in the order they were read
+pub struct Incoming {
+    inner: Arc<Inner>,
+    frames: mpsc::UnboundedReceiver<Result<Vec<u8>, Error>>,
+}
+
+impl AsyncClient {
+    /// Does the init handshake like [`MaelstromClient::new`]
+    pub fn new() -> Result<(Self, Incoming), Error> {
+        Ok(Self::from_client(MaelstromClient::new()?))
+    }
+
+    /// Takes over reading from `client`, which must not have been detached
+    pub fn from_client(mut client: MaelstromClient) -> (Self, Incoming) {
+        let lines = client.take_lines();
+
+        let inner = Arc::new(Inner {
+            node_id: client.node_id(),
+            node_ids: client.node_ids().to_vec(),
+            cluster: client.cluster().clone(),
+            client: Mutex::new(client),
+            pending: Mutex::default(),
+        });
+
+        let (sender, frames) = mpsc::unbounded_channel();
+        let pump = inner.clone();
+        std::thread::spawn(move || pump.pump(lines, sender));
+
+        (
+            Self {
+                inner: inner.clone(),
+            },
+            Incoming { inner, frames },
+        )
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.inner.node_id
+    }
+
+    pub fn node_ids(&self) -> &[NodeId] {
+        &self.inner.node_ids
+    }
+
+    pub fn cluster(&self) -> &Cluster {
+        &self.inner.cluster
+    }
+
+    pub fn write<T: Serialize>(&self, resp: impl Borrow<Response<T>>) -> Result<u32, Error> {
+        self.inner.client.lock().unwrap().write(resp)
+    }
+
+    pub fn write_no_response<T: Serialize>(
+        &self,
+        resp: impl Borrow<Response<T>>,
+    ) -> Result<(), Error> {
+        self.inner.client.lock().unwrap().write_no_response(resp)
+    }
+
+    /// Sends `request` to `dest` and waits for its reply, see [`crate::rpc`]
+    pub async fn call<R: Rpc>(
+        &self,
+        dest: NodeId,
+        request: &R,
+        timeout: Duration,
+    ) -> Result<Result<R::Reply, Failure>, Error> {
+        let (sender, receiver) = oneshot::channel();
+
+        let msg_id = {
+            let mut client = self.inner.client.lock().unwrap();
+            let msg_id = client.message_id().expect("the client is not detached");
+            // registered before writing, so that the reply cannot arrive first
+            self.inner.pending.lock().unwrap().insert(msg_id, sender);
+
+            let resp = Response {
+                dest,
+                in_reply_to: None,
+                payload: Typed(request),
+            };
+            if let Err(err) = client.write(resp) {
+                self.inner.pending.lock().unwrap().remove(&msg_id);
+                return Err(err);
+            }
+
+            msg_id
+        };
+
+        let body = match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(body)) => body,
+            // the sender is dropped when stdin is closed
+            Ok(Err(_)) => return Ok(Err(Failure::Timeout)),
+            Err(_) => {
+                self.inner.pending.lock().unwrap().remove(&msg_id);
+                return Ok(Err(Failure::Timeout));
+            }
+        };
+
+        Ok(rpc::parse_reply::<Typed<R::Reply>>(body).map(|Typed(reply)| reply))
+    }
+}
+
+impl Inner {
+    /// Runs on its own thread, handing replies to the calls waiting for them and
+    /// everything else to [`Incoming`], and polling the interceptors while stdin is quiet
+    fn pump(
+        &self,
+        lines: Receiver<Option<std::io::Result<Vec<u8>>>>,
+        sender: mpsc::UnboundedSender<Result<Vec<u8>, Error>>,
+    ) {
+        #[derive(Deserialize)]
+        struct Reply {
+            body: Body,
+        }
+
+        loop {
+            let line = match lines.recv_timeout(POLL_INTERVAL) {
+                Ok(Some(line)) => line,
+                Ok(None) | Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Err(err) = self.client.lock().unwrap().poll_interceptors() {
+                        let _ = sender.send(Err(err));
+                        break;
+                    }
+                    continue;
+                }
+            };
+
+            let mut frame = match line {
+                Ok(frame) => frame,
+                Err(err) => {
+                    let _ = sender.send(Err(err.into()));
+                    break;
+                }
+            };
+            if frame.ends_with(b"\n") {
+                frame.pop();
+            }
+
+            let frame = match self.client.lock().unwrap().accept_owned(frame) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => continue,
+                Err(err) => {
+                    let _ = sender.send(Err(err));
+                    break;
+                }
+            };
+
+            if let Ok(Reply { body }) = serde_json::from_slice(&frame) {
+                let waiting = body
+                    .get("in_reply_to")
+                    .and_then(serde_json::Value::as_u64)
+                    .and_then(|msg_id| u32::try_from(msg_id).ok())
+                    .and_then(|msg_id| self.pending.lock().unwrap().remove(&msg_id));
+
+                if let Some(waiting) = waiting {
+                    // the call may have timed out in the meantime
+                    let _ = waiting.send(body);
+                    continue;
+                }
+            }
+
+            if sender.send(Ok(frame)).is_err() {
+                break;
+            }
+        }
+
+        self.pending.lock().unwrap().clear();
+    }
+}
+
+impl Incoming {
+    /// The next message for the node, or `None` once stdin is closed or the
+    /// shutdown is triggered
+    ///
+    /// Messages that cannot be deserialized are rejected like [`MaelstromClient::read`] does
+    pub async fn next<T: DeserializeOwned>(&mut self) -> Result<Option<Message<T>>, Error> {
+        loop {
+            // the pump also stops once the shutdown is triggered, but frames it
+            // already read would still be handed out
+            let triggered = self.inner.client.lock().unwrap().shutdown().is_triggered();
+            let frame = if triggered {
+                None
+            } else {
+                self.frames.recv().await
+            };
+
+            let Some(frame) = frame else {
+                self.inner.client.lock().unwrap().shut_down()?;
+                return Ok(None);
+            };
+
+            if let Some(message) = self.inner.client.lock().unwrap().deserialize(&frame?)? {
+                return Ok(Some(message));
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use std::{future::Future, time::Instant};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{fault::FaultInjector, kv::rpc::Read, testing};
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn calls_get_their_reply() {
+        let (client, stdin, wire) = testing::piped_client(0, 2);
+        let (client, _incoming) = AsyncClient::from_client(client);
+
+        // seq-kv answers the request once it is written
+        let kv = std::thread::spawn(move || loop {
+            if let Some(request) = wire.messages().pop() {
+                assert_eq!(request["dest"], "seq-kv");
+                assert_eq!(request["body"]["type"], "read");
+                assert_eq!(request["body"]["key"], "x");
+                stdin.send(&json!({
+                    "src": "seq-kv",
+                    "dest": "n0",
+                    "body": {"type": "read_ok", "value": 3, "in_reply_to": request["body"]["msg_id"]},
+                }));
+                return stdin;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        });
+
+        let reply = block_on(client.call(
+            NodeId::seq_kv(),
+            &Read::<_, u32>::new("x"),
+            Duration::from_secs(5),
+        ));
+        assert_eq!(reply.unwrap().unwrap().value, 3);
+        drop(kv.join().unwrap());
+    }
+
+    #[test]
+    fn unanswered_calls_time_out() {
+        let (client, _stdin, wire) = testing::piped_client(0, 2);
+        let (client, _incoming) = AsyncClient::from_client(client);
+
+        let reply = block_on(client.call(
+            NodeId::seq_kv(),
+            &Read::<_, u32>::new("x"),
+            Duration::from_millis(20),
+        ));
+        assert!(matches!(reply.unwrap(), Err(Failure::Timeout)));
+        assert!(client.inner.pending.lock().unwrap().is_empty());
+        assert_eq!(wire.messages().len(), 1);
+    }
+
+    #[test]
+    fn messages_are_read_until_the_shutdown() {
+        let (client, stdin, _wire) = testing::piped_client(0, 2);
+        let shutdown = client.shutdown().clone();
+        let (_client, mut incoming) = AsyncClient::from_client(client);
+
+        stdin.send(&json!({"src": "c1", "dest": "n0", "body": {"type": "read", "msg_id": 1}}));
+        stdin.send(&json!({"src": "c1", "dest": "n0", "body": {"type": "read", "msg_id": 2}}));
+
+        block_on(async {
+            let message = incoming.next::<serde_json::Value>().await.unwrap().unwrap();
+            assert_eq!(message.msg_id, Some(1));
+
+            shutdown.trigger();
+            assert!(incoming
+                .next::<serde_json::Value>()
+                .await
+                .unwrap()
+                .is_none());
+        });
+    }
+
+    #[test]
+    fn held_frames_are_written_while_idle() {
+        let (mut client, _stdin, wire) = testing::piped_client(1, 3);
+        client.add_interceptor(FaultInjector::new(
+            "delay=1,delay_ms=20..30".parse().unwrap(),
+        ));
+        let (client, _incoming) = AsyncClient::from_client(client);
+
+        let gossip = Response {
+            dest: NodeId::node(2),
+            in_reply_to: None,
+            payload: json!({"type": "gossip"}),
+        };
+        client.write_no_response(&gossip).unwrap();
+        assert_eq!(wire.frames().len(), 0);
+
+        let start = Instant::now();
+        while wire.messages().is_empty() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "the frame was never written"
+            );
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
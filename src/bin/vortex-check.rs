@@ -0,0 +1,53 @@
+use anyhow::Context;
+use vortex::{
+    checker::{broadcast, linearizable, txn},
+    history,
+};
+
+const USAGE: &str = "usage: vortex-check <linearizable|broadcast|txn> <history.jsonl>";
+
+/// vortex-check <checker> <history.jsonl>
+pub fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args_os().skip(1);
+    let (Some(checker), Some(path)) = (args.next(), args.next()) else {
+        anyhow::bail!("{USAGE}");
+    };
+
+    let events =
+        history::read(&path).with_context(|| format!("could not read history {path:?}"))?;
+
+    let valid = match checker.to_str() {
+        Some("linearizable") => {
+            let operations =
+                linearizable::from_history::<u64, u64>(&events).map_err(anyhow::Error::msg)?;
+            match linearizable::check(&operations) {
+                Ok(()) => {
+                    println!("valid");
+                    true
+                }
+                Err(counterexample) => {
+                    print!("{counterexample}");
+                    false
+                }
+            }
+        }
+        Some("broadcast") => {
+            let report = broadcast::check(&events).map_err(anyhow::Error::msg)?;
+            print!("{report}");
+            report.is_valid()
+        }
+        Some("txn") => {
+            let txns = txn::from_history(&events).map_err(anyhow::Error::msg)?;
+            let report = txn::check(txns).map_err(anyhow::Error::msg)?;
+            print!("{report}");
+            report.violated().is_none()
+        }
+        _ => anyhow::bail!("unknown checker {checker:?}\n{USAGE}"),
+    };
+
+    if !valid {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::{ffi::OsString, time::Duration};
+
+use anyhow::Context;
+use vortex::harness::{self, workload, Config};
+
+const USAGE: &str =
+    "usage: vortex-harness <workload> <node binary> [--node-count N] [--concurrency N] \
+[--rate R] [--time-limit SECS] [--timeout SECS] [--recovery SECS] [--seed N] [--history PATH] \
+[--log-stderr] [-- args...]";
+
+fn parse<T: std::str::FromStr>(flag: &str, value: Option<OsString>) -> anyhow::Result<T> {
+    value
+        .and_then(|value| value.into_string().ok())
+        .and_then(|value| value.parse().ok())
+        .with_context(|| format!("{flag} needs a valid value\n{USAGE}"))
+}
+
+fn seconds(flag: &str, value: Option<OsString>) -> anyhow::Result<Duration> {
+    Duration::try_from_secs_f64(parse(flag, value)?)
+        .with_context(|| format!("{flag} needs a finite, non-negative number of seconds\n{USAGE}"))
+}
+
+/// vortex-harness <workload> <node binary> [options] [-- args...]
+pub fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args_os().skip(1);
+    let (Some(name), Some(binary)) = (args.next(), args.next()) else {
+        anyhow::bail!("{USAGE}");
+    };
+
+    let mut config = Config::new(binary);
+    let mut history = None;
+
+    while let Some(flag) = args.next() {
+        match flag.to_str() {
+            Some("--node-count") => config.node_count = parse("--node-count", args.next())?,
+            Some("--concurrency") => config.concurrency = parse("--concurrency", args.next())?,
+            Some("--rate") => config.rate = parse("--rate", args.next())?,
+            Some("--time-limit") => config.time_limit = seconds("--time-limit", args.next())?,
+            Some("--timeout") => config.timeout = seconds("--timeout", args.next())?,
+            Some("--recovery") => config.recovery = seconds("--recovery", args.next())?,
+            Some("--seed") => config.seed = parse("--seed", args.next())?,
+            Some("--history") => history = Some(args.next().context(USAGE)?),
+            Some("--log-stderr") => config.log_stderr = true,
+            Some("--") => {
+                config.args = args.by_ref().collect();
+            }
+            _ => anyhow::bail!("unknown option {flag:?}\n{USAGE}"),
+        }
+    }
+
+    anyhow::ensure!(config.node_count > 0, "--node-count must be positive");
+    anyhow::ensure!(config.rate > 0.0, "--rate must be positive");
+
+    let name = name.to_string_lossy();
+    let Some(mut workload) = workload::by_name(&name, config.seed) else {
+        anyhow::bail!(
+            "unknown workload {name}, expected one of {:?}",
+            workload::NAMES
+        );
+    };
+
+    let report = harness::run(&config, &mut *workload)?;
+    print!("{report}");
+
+    if let Some(path) = history {
+        let events: Vec<_> = report
+            .history
+            .iter()
+            .chain(&report.finals)
+            .cloned()
+            .collect();
+        vortex::history::write(&path, &events)
+            .with_context(|| format!("could not write the history to {path:?}"))?;
+    }
+
+    if !report.passed() {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use std::process::Command;
+
+use anyhow::Context;
+
+/// vortex-replay <transcript.jsonl> <node binary> [args...]
+pub fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args_os().skip(1);
+    let (Some(transcript), Some(binary)) = (args.next(), args.next()) else {
+        anyhow::bail!("usage: vortex-replay <transcript.jsonl> <node binary> [args...]");
+    };
+
+    let transcript = vortex::transcript::read(&transcript)
+        .with_context(|| format!("could not read transcript {transcript:?}"))?;
+
+    let report = vortex::replay::replay_command(&transcript, Command::new(binary).args(args))?;
+    print!("{report}");
+
+    if report.divergence.is_some() {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use std::{ffi::OsString, path::Path};
+
+use vortex::{
+    config::{self, Config},
+    workloads::{self, Entry},
+};
+
+/// vortex <workload> [--param value ...]
+///
+/// Maelstrom cannot pass arguments, so the workload can also be picked with
+/// `VORTEX_WORKLOAD`, or by the name the binary was invoked as, e.g. through a
+/// symlink named `echo`.
+pub fn main() -> anyhow::Result<()> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let program = args.remove(0);
+
+    let subcommand = match args.first().and_then(|arg| arg.to_str()) {
+        Some(name) if !name.starts_with("--") => Some(name.to_owned()),
+        _ => None,
+    };
+
+    let entry = match subcommand {
+        Some(name) => {
+            args.remove(0);
+            workloads::find(&name).ok_or_else(|| usage(&format!("unknown workload {name}")))?
+        }
+        None => match std::env::var(config::env_var("workload")) {
+            Ok(name) => {
+                workloads::find(&name).ok_or_else(|| usage(&format!("unknown workload {name}")))?
+            }
+            Err(_) => Path::new(&program)
+                .file_stem()
+                .and_then(|name| workloads::find(name.to_str()?))
+                .ok_or_else(|| usage("no workload given"))?,
+        },
+    };
+
+    if args.iter().any(|arg| arg == "--help") {
+        println!("{}: {}", entry.name, entry.about);
+        return Ok(());
+    }
+
+    entry.run(&Config::from_args(args)?)?;
+
+    Ok(())
+}
+
+fn usage(problem: &str) -> anyhow::Error {
+    let mut usage =
+        format!("{problem}\n\nusage: vortex <workload> [--param value ...]\n\nworkloads:\n");
+    for Entry { name, about, .. } in workloads::REGISTRY {
+        usage += &format!("    {name:<20}{about}\n");
+    }
+
+    anyhow::Error::msg(usage)
+}
@@ -0,0 +1,5 @@
+//! Checkers for the histories recorded by the [`harness`](crate::harness), see [`crate::history`]
+
+pub mod broadcast;
+pub mod linearizable;
+pub mod txn;
@@ -0,0 +1,325 @@
+//! Checks that every acknowledged broadcast message reaches every node
+//!
+//! A message is lost if the last read of some node that started after the
+//! message was acknowledged does not have it. It is stale if a read that
+//! started after it was acknowledged missed it, but every node had it in the
+//! end. Its stable latency is the time from the broadcast until no read missed
+//! it anymore, which is the time it took to become visible everywhere.
+//!
+//! A node whose last read started before a message was acknowledged cannot
+//! lose it. If that read missed the message, it is neither lost nor stale and
+//! has no stable latency, since it is not known to have reached every node.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    time::Duration,
+};
+
+use serde_json::Value;
+
+use crate::{
+    history::{self, Event, EventKind},
+    metrics::{Histogram, LatencySummary},
+    NodeId,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub invoked: u64,
+    /// When the broadcast was acknowledged, if it was
+    pub acknowledged: Option<u64>,
+    /// The time from the broadcast until every later read had it, if every
+    /// node had it in the end
+    pub stable_latency: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub messages: BTreeMap<u64, Message>,
+    /// Acknowledged messages that some node did not have in the end
+    pub lost: BTreeSet<u64>,
+    /// Messages that a read missed after they were acknowledged, but were not lost
+    pub stale: BTreeSet<u64>,
+    /// Messages that were read but never broadcast
+    pub unexpected: BTreeSet<u64>,
+    pub stable_latency: LatencySummary,
+}
+
+impl Report {
+    pub fn is_valid(&self) -> bool {
+        self.lost.is_empty() && self.unexpected.is_empty()
+    }
+}
+
+struct Read {
+    node: NodeId,
+    invoked: u64,
+    completed: u64,
+    messages: BTreeSet<u64>,
+}
+
+/// Checks the `broadcast` and `read` operations in a history, other operations are ignored
+pub fn check(events: &[Event]) -> Result<Report, String> {
+    let mut messages = BTreeMap::new();
+    let mut reads = Vec::new();
+
+    for operation in history::operations(events) {
+        match operation.ty() {
+            "broadcast" => {
+                let message = operation
+                    .request
+                    .get("message")
+                    .and_then(Value::as_u64)
+                    .ok_or("a broadcast without a message")?;
+
+                let acknowledged = operation
+                    .completed
+                    .filter(|_| operation.kind == EventKind::Ok);
+                messages.insert(
+                    message,
+                    Message {
+                        invoked: operation.invoked,
+                        acknowledged,
+                        stable_latency: None,
+                    },
+                );
+            }
+            "read" if operation.kind == EventKind::Ok => {
+                let read = operation
+                    .reply
+                    .as_ref()
+                    .and_then(|reply| reply.get("messages"))
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| format!("a read_ok without messages from {}", operation.node))?;
+
+                reads.push(Read {
+                    node: operation.node,
+                    invoked: operation.invoked,
+                    completed: operation.completed.unwrap_or(operation.invoked),
+                    messages: read.iter().filter_map(Value::as_u64).collect(),
+                });
+            }
+            _ => (),
+        }
+    }
+
+    let mut last_reads: BTreeMap<NodeId, &Read> = BTreeMap::new();
+    for read in &reads {
+        let last = last_reads.entry(read.node).or_insert(read);
+        if read.invoked > last.invoked {
+            *last = read;
+        }
+    }
+
+    let mut report = Report {
+        messages: BTreeMap::new(),
+        lost: BTreeSet::new(),
+        stale: BTreeSet::new(),
+        unexpected: reads
+            .iter()
+            .flat_map(|read| &read.messages)
+            .filter(|message| !messages.contains_key(message))
+            .copied()
+            .collect(),
+        stable_latency: Histogram::default().summary(),
+    };
+
+    let mut latency = Histogram::default();
+    for (&value, message) in &mut messages {
+        let missed_by = |read: &&Read| !read.messages.contains(&value);
+
+        let lost = message.acknowledged.is_some_and(|acknowledged| {
+            last_reads
+                .values()
+                .filter(|read| read.invoked > acknowledged)
+                .any(missed_by)
+        });
+        if lost {
+            report.lost.insert(value);
+            continue;
+        }
+
+        let everywhere = !last_reads.is_empty() && !last_reads.values().any(missed_by);
+        if !everywhere {
+            continue;
+        }
+
+        if let Some(acknowledged) = message.acknowledged {
+            if reads
+                .iter()
+                .filter(|read| read.invoked > acknowledged)
+                .any(|read| missed_by(&read))
+            {
+                report.stale.insert(value);
+            }
+        }
+
+        let stable = reads
+            .iter()
+            .filter(missed_by)
+            .map(|read| read.completed)
+            .max()
+            .unwrap_or(message.invoked)
+            .max(message.invoked);
+        message.stable_latency = Some(stable - message.invoked);
+        latency.record(Duration::from_micros(stable - message.invoked));
+    }
+
+    report.messages = messages;
+    report.stable_latency = latency.summary();
+
+    Ok(report)
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let acknowledged = self
+            .messages
+            .values()
+            .filter(|message| message.acknowledged.is_some())
+            .count();
+        writeln!(
+            f,
+            "broadcast: {} messages, {} acknowledged",
+            self.messages.len(),
+            acknowledged
+        )?;
+
+        let latency = &self.stable_latency;
+        if latency.count > 0 {
+            writeln!(
+                f,
+                "stable latency: p50 {}us, p90 {}us, p99 {}us, max {}us",
+                latency.p50_us, latency.p90_us, latency.p99_us, latency.max_us
+            )?;
+        }
+
+        writeln!(f, "lost: {:?}", self.lost)?;
+        writeln!(f, "stale: {:?}", self.stale)?;
+        if !self.unexpected.is_empty() {
+            writeln!(f, "unexpected: {:?}", self.unexpected)?;
+        }
+
+        match self.is_valid() {
+            true => writeln!(f, "valid"),
+            false => writeln!(f, "invalid"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Builds the events of one process per operation
+    #[derive(Default)]
+    struct History {
+        events: Vec<Event>,
+    }
+
+    impl History {
+        fn operation(&mut self, node: u32, span: (u64, u64), request: Value, reply: Value) {
+            let process = self.events.len() as u32;
+            let event = |ts, kind, body: Value| Event {
+                ts,
+                process,
+                kind,
+                node: NodeId::node(node),
+                body: match body {
+                    Value::Object(body) => body,
+                    _ => unreachable!(),
+                },
+            };
+            self.events.push(event(span.0, EventKind::Invoke, request));
+            self.events.push(event(span.1, EventKind::Ok, reply));
+        }
+
+        fn broadcast(&mut self, node: u32, span: (u64, u64), message: u64) {
+            let request = json!({"type": "broadcast", "message": message});
+            self.operation(node, span, request, json!({"type": "broadcast_ok"}));
+        }
+
+        fn read(&mut self, node: u32, span: (u64, u64), messages: &[u64]) {
+            let reply = json!({"type": "read_ok", "messages": messages});
+            self.operation(node, span, json!({"type": "read"}), reply);
+        }
+
+        fn check(&self) -> Report {
+            check(&self.events).unwrap()
+        }
+    }
+
+    #[test]
+    fn messages_on_every_node_are_valid() {
+        let mut history = History::default();
+        history.broadcast(0, (0, 10), 1);
+        history.read(0, (20, 30), &[1]);
+        history.read(1, (20, 30), &[1]);
+
+        let report = history.check();
+        assert!(report.is_valid());
+        assert!(report.lost.is_empty() && report.stale.is_empty());
+        assert_eq!(report.messages[&1].acknowledged, Some(10));
+        assert_eq!(report.messages[&1].stable_latency, Some(0));
+    }
+
+    #[test]
+    fn messages_missing_from_a_last_read_are_lost() {
+        let mut history = History::default();
+        history.broadcast(0, (0, 10), 1);
+        history.broadcast(0, (0, 10), 2);
+        history.read(0, (20, 30), &[1, 2]);
+        history.read(1, (20, 30), &[2]);
+
+        let report = history.check();
+        assert!(!report.is_valid());
+        assert_eq!(report.lost, BTreeSet::from([1]));
+        assert_eq!(report.messages[&1].stable_latency, None);
+        assert!(report.to_string().ends_with("invalid\n"));
+    }
+
+    #[test]
+    fn messages_missed_by_earlier_reads_are_stale() {
+        let mut history = History::default();
+        history.broadcast(0, (0, 10), 1);
+        history.read(1, (20, 30), &[]);
+        history.read(1, (40, 50), &[1]);
+        history.read(0, (40, 50), &[1]);
+
+        let report = history.check();
+        assert!(report.is_valid());
+        assert!(report.lost.is_empty());
+        assert_eq!(report.stale, BTreeSet::from([1]));
+        // visible everywhere once the read that missed it completed
+        assert_eq!(report.messages[&1].stable_latency, Some(30));
+    }
+
+    #[test]
+    fn messages_that_were_never_broadcast_are_unexpected() {
+        let mut history = History::default();
+        history.broadcast(0, (0, 10), 1);
+        history.read(0, (20, 30), &[1, 7]);
+
+        let report = history.check();
+        assert!(!report.is_valid());
+        assert_eq!(report.unexpected, BTreeSet::from([7]));
+        assert!(report.lost.is_empty());
+    }
+
+    #[test]
+    fn nodes_not_read_after_the_ack_neither_lose_nor_count_messages() {
+        let mut history = History::default();
+        history.read(1, (0, 50), &[]);
+        history.broadcast(0, (10, 20), 1);
+        history.read(0, (30, 40), &[1]);
+
+        let report = history.check();
+        assert!(report.is_valid());
+        assert!(report.lost.is_empty() && report.stale.is_empty());
+        assert_eq!(report.messages[&1].stable_latency, None);
+        assert_eq!(report.stable_latency.count, 0);
+    }
+}
@@ -0,0 +1,706 @@
+//! Checks that reads, writes and compare and sets on a key value store are linearizable
+//!
+//! Every key is checked on its own, with the search of Wing and Gong as improved
+//! by Lowe and used by Porcupine: operations are linearized in the order of their
+//! invocations, backtracking whenever an operation returned before it could be
+//! linearized, and skipping states that were already explored.
+//!
+//! Operations that failed definitely did not happen and are left out. Operations
+//! whose outcome is unknown may have happened at any time after they were
+//! invoked, or not at all.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::{self, Debug},
+    hash::Hash,
+};
+
+use serde::de::DeserializeOwned;
+
+use crate::{
+    history::{self, Event, EventKind},
+    kv::rpc::{Cas, Read, ReadOk, Write},
+    ErrorCode, ErrorPayload,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request<K, V> {
+    Read(Read<K, V>),
+    Write(Write<K, V>),
+    Cas(Cas<K, V>),
+}
+
+impl<K, V> Request<K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            Self::Read(read) => &read.key,
+            Self::Write(write) => &write.key,
+            Self::Cas(cas) => &cas.key,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome<V> {
+    /// A write or compare and set that took effect
+    Written,
+    /// A read of the value, `None` if the key did not exist
+    Read(Option<V>),
+    /// The request may or may not have taken effect
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation<K, V> {
+    pub process: u32,
+    pub request: Request<K, V>,
+    pub invoked: u64,
+    /// `None` if the outcome is unknown
+    pub completed: Option<u64>,
+    pub outcome: Outcome<V>,
+}
+
+impl<K, V> Operation<K, V> {
+    fn is_unknown(&self) -> bool {
+        self.completed.is_none() || matches!(self.outcome, Outcome::Unknown)
+    }
+}
+
+/// A history of one key that is not linearizable
+#[derive(Debug, Clone, PartialEq)]
+pub struct Counterexample<K, V> {
+    pub key: K,
+    /// The operations on the key that are needed for the violation, a prefix of
+    /// the history without the reads that do not matter
+    pub operations: Vec<Operation<K, V>>,
+    /// The longest order of `operations` that is consistent, by index
+    pub linearized: Vec<usize>,
+    /// The value of the key after `linearized`
+    pub value: Option<V>,
+    /// The operation that completed but cannot be linearized after `linearized`
+    pub stuck: usize,
+}
+
+/// Checks every key on its own, returns the first key that is not linearizable
+pub fn check<K, V>(operations: &[Operation<K, V>]) -> Result<(), Counterexample<K, V>>
+where
+    K: Ord + Clone,
+    V: Hash + Eq + Clone,
+{
+    let mut keys: BTreeMap<&K, Vec<Operation<K, V>>> = BTreeMap::new();
+    for operation in operations {
+        keys.entry(operation.request.key())
+            .or_default()
+            .push(operation.clone());
+    }
+
+    for (key, operations) in keys {
+        if search(&operations).is_err() {
+            return Err(shrink(key.clone(), operations));
+        }
+    }
+
+    Ok(())
+}
+
+/// The operations on a key value service in a history recorded by the harness
+///
+/// Reads that failed with `key-does-not-exist` (20) read a missing key, other
+/// definite errors are left out
+pub fn from_history<K, V>(events: &[Event]) -> Result<Vec<Operation<K, V>>, String>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+{
+    let parse = |body: &history::Body| {
+        let body = serde_json::Value::Object(body.clone());
+        let request = match body.get("type").and_then(serde_json::Value::as_str) {
+            Some("read") => serde_json::from_value(body.clone()).map(Request::Read),
+            Some("write") => serde_json::from_value(body.clone()).map(Request::Write),
+            Some("cas") => serde_json::from_value(body.clone()).map(Request::Cas),
+            _ => return Err(format!("not a kv request: {body}")),
+        };
+
+        request.map_err(|err| format!("invalid kv request {body}: {err}"))
+    };
+
+    let mut operations = Vec::new();
+    for operation in history::operations(events) {
+        let request = parse(&operation.request)?;
+        let reply = operation.reply.map(serde_json::Value::Object);
+
+        let outcome = match (operation.kind, reply) {
+            (EventKind::Ok, Some(reply)) => match request {
+                Request::Read(_) => {
+                    let ReadOk { value } = serde_json::from_value(reply.clone())
+                        .map_err(|err| format!("invalid read reply {reply}: {err}"))?;
+                    Outcome::Read(Some(value))
+                }
+                Request::Write(_) | Request::Cas(_) => Outcome::Written,
+            },
+            (EventKind::Fail, Some(reply)) => {
+                let error: ErrorPayload = serde_json::from_value(reply.clone())
+                    .map_err(|err| format!("invalid error reply {reply}: {err}"))?;
+
+                match request {
+                    Request::Read(_) if error.code == ErrorCode::KEY_DOES_NOT_EXIST => {
+                        Outcome::Read(None)
+                    }
+                    _ => continue,
+                }
+            }
+            _ => Outcome::Unknown,
+        };
+
+        operations.push(Operation {
+            process: operation.process,
+            request,
+            invoked: operation.invoked,
+            completed: operation
+                .completed
+                .filter(|_| !matches!(outcome, Outcome::Unknown)),
+            outcome,
+        });
+    }
+
+    Ok(operations)
+}
+
+/// Applies an operation to the value of its key, `None` if it is not possible
+fn apply<K, V: Clone + PartialEq>(
+    value: &Option<V>,
+    operation: &Operation<K, V>,
+) -> Option<Option<V>> {
+    match (&operation.request, &operation.outcome) {
+        (Request::Read(_), Outcome::Read(read)) => (read == value).then(|| value.clone()),
+        (Request::Read(_), _) => Some(value.clone()),
+        (Request::Write(write), _) => Some(Some(write.value.clone())),
+        (Request::Cas(cas), _) => match value {
+            Some(value) if *value == cas.from => Some(Some(cas.to.clone())),
+            None if cas.create_if_not_exists => Some(Some(cas.to.clone())),
+            _ => None,
+        },
+    }
+}
+
+/// The longest consistent order found, and the operation that got stuck after it
+struct Failure<V> {
+    linearized: Vec<usize>,
+    value: Option<V>,
+    stuck: usize,
+}
+
+/// Searches for a linearization of the operations on one key
+fn search<K, V>(operations: &[Operation<K, V>]) -> Result<(), Failure<V>>
+where
+    V: Hash + Eq + Clone,
+{
+    // reads with an unknown outcome have no effect
+    let relevant: Vec<usize> = (0..operations.len())
+        .filter(|&index| {
+            let operation = &operations[index];
+            !(operation.is_unknown() && matches!(operation.request, Request::Read(_)))
+        })
+        .collect();
+
+    // calls and returns in time order, with calls first when they are at the same time,
+    // operations with an unknown outcome return after everything else
+    let mut events: Vec<(u64, bool, usize)> = Vec::with_capacity(relevant.len() * 2);
+    for &index in &relevant {
+        let operation = &operations[index];
+        let returned = match operation.completed {
+            Some(completed) if !operation.is_unknown() => completed,
+            _ => u64::MAX,
+        };
+        events.push((operation.invoked, false, index));
+        events.push((returned.max(operation.invoked), true, index));
+    }
+    events.sort_by_key(|&(time, is_return, _)| (time, is_return));
+
+    // a doubly linked list of the events, with `head` as the sentinel
+    let head = events.len();
+    let mut next: Vec<usize> = (1..=head).chain([0]).collect();
+    let mut prev: Vec<usize> = [head].into_iter().chain(0..head).collect();
+
+    let mut returns = vec![0; operations.len()];
+    for (position, &(_, is_return, index)) in events.iter().enumerate() {
+        if is_return {
+            returns[index] = position;
+        }
+    }
+
+    let unlink = |next: &mut Vec<usize>, prev: &mut Vec<usize>, entry: usize| {
+        next[prev[entry]] = next[entry];
+        prev[next[entry]] = prev[entry];
+    };
+    let relink = |next: &mut Vec<usize>, prev: &mut Vec<usize>, entry: usize| {
+        next[prev[entry]] = entry;
+        prev[next[entry]] = entry;
+    };
+
+    let mut linearized = vec![false; operations.len()];
+    let mut value = None;
+    let mut stack: Vec<(usize, Option<V>)> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut longest: Option<Failure<V>> = None;
+
+    let mut entry = next[head];
+    loop {
+        if entry == head {
+            return Ok(());
+        }
+
+        let (_, is_return, index) = events[entry];
+        if !is_return {
+            if let Some(applied) = apply(&value, &operations[index]) {
+                linearized[index] = true;
+                if seen.insert((linearized.clone(), applied.clone())) {
+                    stack.push((entry, std::mem::replace(&mut value, applied)));
+                    unlink(&mut next, &mut prev, entry);
+                    unlink(&mut next, &mut prev, returns[index]);
+                    entry = next[head];
+                    continue;
+                }
+                linearized[index] = false;
+            }
+
+            entry = next[entry];
+            continue;
+        }
+
+        // every operation left returns after this one, and may not have happened
+        if operations[index].is_unknown() {
+            return Ok(());
+        }
+
+        if longest
+            .as_ref()
+            .is_none_or(|longest| stack.len() > longest.linearized.len())
+        {
+            longest = Some(Failure {
+                linearized: stack.iter().map(|&(entry, _)| events[entry].2).collect(),
+                value: value.clone(),
+                stuck: index,
+            });
+        }
+
+        let Some((call, previous)) = stack.pop() else {
+            return Err(longest.expect("the longest order was just recorded"));
+        };
+        let (_, _, undone) = events[call];
+        linearized[undone] = false;
+        value = previous;
+        relink(&mut next, &mut prev, returns[undone]);
+        relink(&mut next, &mut prev, call);
+        entry = next[call];
+    }
+}
+
+/// Shrinks a history that is not linearizable to its shortest prefix that is not,
+/// and leaves out the reads that are not needed for the violation
+fn shrink<K, V>(key: K, mut operations: Vec<Operation<K, V>>) -> Counterexample<K, V>
+where
+    K: Clone,
+    V: Hash + Eq + Clone,
+{
+    operations.sort_by_key(|operation| operation.invoked);
+
+    let mut completions: Vec<u64> = operations
+        .iter()
+        .filter(|operation| !operation.is_unknown())
+        .filter_map(|operation| operation.completed)
+        .collect();
+    completions.sort_unstable();
+
+    // a longer prefix of a history that is not linearizable is not either
+    let (mut low, mut high) = (0, completions.len().saturating_sub(1));
+    while low < high {
+        let middle = (low + high) / 2;
+        match search(&prefix(&operations, completions[middle])) {
+            Ok(()) => low = middle + 1,
+            Err(_) => high = middle,
+        }
+    }
+    if let Some(&until) = completions.get(low) {
+        let shorter = prefix(&operations, until);
+        if search(&shorter).is_err() {
+            operations = shorter;
+        }
+    }
+
+    // leaving out a read only removes a constraint
+    let mut index = 0;
+    while index < operations.len() {
+        if matches!(operations[index].request, Request::Read(_)) {
+            let read = operations.remove(index);
+            if search(&operations).is_err() {
+                continue;
+            }
+            operations.insert(index, read);
+        }
+        index += 1;
+    }
+
+    let Err(failure) = search(&operations) else {
+        unreachable!("the shrunk history is still not linearizable")
+    };
+
+    Counterexample {
+        key,
+        operations,
+        linearized: failure.linearized,
+        value: failure.value,
+        stuck: failure.stuck,
+    }
+}
+
+/// The operations invoked until `until`, the ones that completed after it are still
+/// pending
+fn prefix<K: Clone, V: Clone>(operations: &[Operation<K, V>], until: u64) -> Vec<Operation<K, V>> {
+    operations
+        .iter()
+        .filter(|operation| operation.invoked <= until)
+        .map(|operation| {
+            let mut operation = operation.clone();
+            if operation
+                .completed
+                .is_some_and(|completed| completed > until)
+            {
+                operation.completed = None;
+                operation.outcome = Outcome::Unknown;
+            }
+            operation
+        })
+        .collect()
+}
+
+impl<K: Debug, V: Debug> fmt::Display for Request<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(_) => write!(f, "read"),
+            Self::Write(write) => write!(f, "write {:?}", write.value),
+            Self::Cas(cas) => write!(f, "cas {:?} -> {:?}", cas.from, cas.to),
+        }
+    }
+}
+
+impl<K: Debug, V: Debug> fmt::Display for Counterexample<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "key {:?} is not linearizable:", self.key)?;
+
+        for (index, operation) in self.operations.iter().enumerate() {
+            write!(
+                f,
+                "  [{index}] process {} {}",
+                operation.process, operation.request
+            )?;
+            match &operation.outcome {
+                Outcome::Read(Some(value)) => write!(f, " = {value:?}")?,
+                Outcome::Read(None) => write!(f, " = missing")?,
+                Outcome::Written => (),
+                Outcome::Unknown => write!(f, " (unknown)")?,
+            }
+            match operation.completed {
+                Some(completed) => writeln!(f, " at {}..{}us", operation.invoked, completed)?,
+                None => writeln!(f, " at {}us..", operation.invoked)?,
+            }
+        }
+
+        writeln!(
+            f,
+            "  linearized {:?}, leaving {:?}",
+            self.linearized, self.value
+        )?;
+        writeln!(f, "  [{}] cannot be linearized after that", self.stuck)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::NodeId;
+
+    fn operation(
+        process: u32,
+        request: Request<u32, u32>,
+        invoked: u64,
+        completed: Option<u64>,
+        outcome: Outcome<u32>,
+    ) -> Operation<u32, u32> {
+        Operation {
+            process,
+            request,
+            invoked,
+            completed,
+            outcome,
+        }
+    }
+
+    fn write(process: u32, value: u32, invoked: u64, completed: u64) -> Operation<u32, u32> {
+        let request = Request::Write(Write { key: 0, value });
+        operation(process, request, invoked, Some(completed), Outcome::Written)
+    }
+
+    fn read(process: u32, value: Option<u32>, invoked: u64, completed: u64) -> Operation<u32, u32> {
+        let request = Request::Read(Read::new(0));
+        operation(
+            process,
+            request,
+            invoked,
+            Some(completed),
+            Outcome::Read(value),
+        )
+    }
+
+    fn cas(from: u32, to: u32) -> Request<u32, u32> {
+        Request::Cas(Cas {
+            key: 0,
+            from,
+            to,
+            create_if_not_exists: false,
+        })
+    }
+
+    fn unknown(process: u32, request: Request<u32, u32>, invoked: u64) -> Operation<u32, u32> {
+        operation(process, request, invoked, None, Outcome::Unknown)
+    }
+
+    #[test]
+    fn linearizable_register_histories() {
+        let histories = [
+            vec![write(1, 1, 0, 10), read(2, Some(1), 20, 30)],
+            // a read before the first write sees a missing key
+            vec![read(1, None, 0, 10), write(2, 1, 20, 30)],
+            // concurrent writes can take effect in either order
+            vec![
+                write(1, 1, 0, 30),
+                write(2, 2, 10, 20),
+                read(3, Some(1), 40, 50),
+            ],
+            // a read concurrent with a write sees either value
+            vec![
+                write(1, 1, 0, 10),
+                write(2, 2, 20, 50),
+                read(3, Some(1), 25, 30),
+                read(4, Some(2), 30, 40),
+                read(3, Some(2), 60, 70),
+            ],
+            vec![
+                write(1, 1, 0, 10),
+                operation(2, cas(1, 2), 20, Some(30), Outcome::Written),
+                read(3, Some(2), 40, 50),
+            ],
+        ];
+
+        for operations in histories {
+            assert!(search(&operations).is_ok(), "{operations:?}");
+            assert_eq!(check(&operations), Ok(()));
+        }
+    }
+
+    #[test]
+    fn non_linearizable_register_histories() {
+        let histories = [
+            // a stale read after the second write completed
+            vec![
+                write(1, 1, 0, 10),
+                write(2, 2, 20, 30),
+                read(3, Some(1), 40, 50),
+            ],
+            // a read of a value that was never written
+            vec![write(1, 1, 0, 10), read(2, Some(2), 20, 30)],
+            // the key went missing after a write
+            vec![write(1, 1, 0, 10), read(2, None, 20, 30)],
+            // two reads see the writes in different orders
+            vec![
+                write(1, 1, 0, 100),
+                write(2, 2, 0, 100),
+                read(3, Some(1), 10, 20),
+                read(4, Some(2), 30, 40),
+                read(3, Some(1), 50, 60),
+            ],
+            // a compare and set from a value the key did not have
+            vec![
+                write(1, 1, 0, 10),
+                operation(2, cas(2, 3), 20, Some(30), Outcome::Written),
+            ],
+        ];
+
+        for operations in histories {
+            assert!(search(&operations).is_err(), "{operations:?}");
+        }
+    }
+
+    #[test]
+    fn unknown_writes_may_happen_any_time_after_their_invocation() {
+        let write_one = || unknown(1, Request::Write(Write { key: 0, value: 1 }), 0);
+
+        // it took effect long after it was invoked
+        let late = vec![
+            write_one(),
+            read(2, None, 10, 20),
+            read(2, Some(1), 100, 110),
+        ];
+        assert!(search(&late).is_ok());
+
+        // or not at all
+        let never = vec![write_one(), read(2, None, 100, 110)];
+        assert!(search(&never).is_ok());
+
+        // but not before it was invoked
+        let early = vec![
+            read(2, Some(1), 0, 10),
+            unknown(1, Request::Write(Write { key: 0, value: 1 }), 20),
+        ];
+        assert!(search(&early).is_err());
+
+        // and not twice
+        let undone = vec![
+            write(3, 2, 0, 5),
+            write_one(),
+            read(2, Some(1), 10, 20),
+            read(2, Some(2), 30, 40),
+        ];
+        assert!(search(&undone).is_err());
+    }
+
+    #[test]
+    fn unknown_compare_and_sets_only_happen_from_their_value() {
+        let applied = vec![
+            write(1, 1, 0, 10),
+            unknown(2, cas(1, 2), 20),
+            read(3, Some(2), 30, 40),
+        ];
+        assert!(search(&applied).is_ok());
+
+        let not_applied = vec![
+            write(1, 1, 0, 10),
+            unknown(2, cas(1, 2), 20),
+            read(3, Some(1), 30, 40),
+        ];
+        assert!(search(&not_applied).is_ok());
+
+        let impossible = vec![
+            write(1, 1, 0, 10),
+            unknown(2, cas(3, 2), 20),
+            read(3, Some(2), 30, 40),
+        ];
+        assert!(search(&impossible).is_err());
+    }
+
+    #[test]
+    fn shrunk_counterexamples_still_fail() {
+        let mut operations = vec![write(1, 1, 0, 10)];
+        for index in 0..20 {
+            operations.push(read(2, Some(1), 20 + index * 10, 25 + index * 10));
+        }
+        operations.push(write(1, 2, 300, 310));
+        operations.push(read(2, Some(2), 320, 330));
+        // the violation
+        operations.push(read(3, Some(1), 340, 350));
+        for index in 0..20u32 {
+            operations.push(write(
+                1,
+                index,
+                400 + u64::from(index) * 10,
+                405 + u64::from(index) * 10,
+            ));
+        }
+
+        let Err(counterexample) = check(&operations) else {
+            panic!("the history is not linearizable");
+        };
+        assert_eq!(counterexample.key, 0);
+        assert!(search(&counterexample.operations).is_err());
+        assert!(
+            counterexample.operations.len() <= 4,
+            "{:?}",
+            counterexample.operations
+        );
+        assert!(counterexample
+            .operations
+            .iter()
+            .all(|operation| operation.invoked <= 350));
+
+        let stuck = &counterexample.operations[counterexample.stuck];
+        assert_eq!(stuck.outcome, Outcome::Read(Some(1)));
+        assert!(counterexample.to_string().contains("cannot be linearized"));
+    }
+
+    #[test]
+    fn keys_are_checked_on_their_own() {
+        let mut operations = vec![write(1, 1, 0, 10), read(2, Some(1), 20, 30)];
+        // the same values on another key are not linearizable
+        let mut other = [write(1, 1, 40, 50), read(2, Some(2), 60, 70)];
+        for operation in &mut other {
+            match &mut operation.request {
+                Request::Read(read) => read.key = 1,
+                Request::Write(write) => write.key = 1,
+                Request::Cas(cas) => cas.key = 1,
+            }
+        }
+        operations.extend(other);
+
+        let Err(counterexample) = check(&operations) else {
+            panic!("key 1 is not linearizable");
+        };
+        assert_eq!(counterexample.key, 1);
+    }
+
+    #[test]
+    fn missing_keys_and_failures_from_history() {
+        let event = |ts, process, kind, body: serde_json::Value| Event {
+            ts,
+            process,
+            kind,
+            node: NodeId::node(0),
+            body: match body {
+                serde_json::Value::Object(body) => body,
+                _ => unreachable!(),
+            },
+        };
+        let events = [
+            event(0, 1, EventKind::Invoke, json!({"type": "read", "key": 0})),
+            event(
+                10,
+                1,
+                EventKind::Fail,
+                json!({"type": "error", "code": 20, "text": "missing"}),
+            ),
+            event(
+                20,
+                1,
+                EventKind::Invoke,
+                json!({"type": "cas", "key": 0, "from": 1, "to": 2}),
+            ),
+            event(
+                30,
+                1,
+                EventKind::Fail,
+                json!({"type": "error", "code": 22, "text": "not 1"}),
+            ),
+            event(
+                40,
+                1,
+                EventKind::Invoke,
+                json!({"type": "write", "key": 0, "value": 3}),
+            ),
+            event(50, 1, EventKind::Info, json!({})),
+            event(60, 2, EventKind::Invoke, json!({"type": "read", "key": 0})),
+            event(70, 2, EventKind::Ok, json!({"type": "read_ok", "value": 3})),
+        ];
+
+        let operations = from_history::<u32, u32>(&events).unwrap();
+        assert_eq!(
+            operations,
+            [
+                read(1, None, 0, 10),
+                unknown(1, Request::Write(Write { key: 0, value: 3 }), 40),
+                read(2, Some(3), 60, 70),
+            ]
+        );
+        assert_eq!(check(&operations), Ok(()));
+    }
+}
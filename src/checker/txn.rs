@@ -0,0 +1,742 @@
+//! Finds isolation anomalies in histories of read write register transactions
+//!
+//! Like Elle, the checker infers the dependencies between transactions from the
+//! values they read and wrote, which requires every write to a key to write a
+//! unique value:
+//!
+//! - `ww`: a transaction read the value another one wrote, then overwrote it
+//! - `wr`: a transaction read the value another one wrote
+//! - `rw`: a transaction read a value that another one overwrote
+//!
+//! Cycles in the dependency graph are anomalies, named after Adya: G0 cycles
+//! only have `ww` edges, G1c cycles have `ww` and `wr` edges, G-single cycles
+//! have exactly one `rw` edge and G2 cycles more. Reading the write of an aborted
+//! transaction is G1a, and reading a write that its transaction overwrote is G1b.
+//! Transactions that read the same version of a key and both overwrote it lost an
+//! update, the order they were installed in is not known.
+//!
+//! Writes to a key that the transaction did not read first are blind, the version
+//! they overwrote is not known, so they have no `ww` or `rw` edges. Cycles through
+//! blind writes are not found, in particular G0 is only found for transactions
+//! that read every key they wrote.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+};
+
+use serde_json::Value;
+
+use crate::history::{self, Event, EventKind};
+
+/// A micro operation of a transaction, the value of a read is `None` if the key
+/// had not been written yet, or if the read did not complete
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mop {
+    Read { key: u64, value: Option<u64> },
+    Write { key: u64, value: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Status {
+    Committed,
+    Aborted,
+    /// The transaction may or may not have committed
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Txn {
+    pub process: u32,
+    pub mops: Vec<Mop>,
+    pub status: Status,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dependency {
+    Ww,
+    Wr,
+    Rw,
+}
+
+/// The transactions of a cycle, by index, each one depending on the previous
+/// one, and the first one on the last one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cycle {
+    pub steps: Vec<(usize, Dependency)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Anomaly {
+    G0(Cycle),
+    /// `reader` read a value written by `writer`, which aborted
+    G1a {
+        reader: usize,
+        writer: usize,
+        key: u64,
+        value: u64,
+    },
+    /// `reader` read a value that `writer` overwrote in the same transaction
+    G1b {
+        reader: usize,
+        writer: usize,
+        key: u64,
+        value: u64,
+    },
+    G1c(Cycle),
+    /// The committed `writers` all read `value` of `key` and overwrote it
+    LostUpdate {
+        writers: Vec<usize>,
+        key: u64,
+        value: Option<u64>,
+    },
+    GSingle(Cycle),
+    G2(Cycle),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IsolationLevel {
+    ReadUncommitted,
+    ReadCommitted,
+    SnapshotIsolation,
+    Serializable,
+}
+
+impl Anomaly {
+    /// The weakest isolation level that forbids the anomaly
+    pub fn violates(&self) -> IsolationLevel {
+        match self {
+            Self::G0(_) => IsolationLevel::ReadUncommitted,
+            Self::G1a { .. } | Self::G1b { .. } | Self::G1c(_) => IsolationLevel::ReadCommitted,
+            Self::LostUpdate { .. } | Self::GSingle(_) => IsolationLevel::SnapshotIsolation,
+            Self::G2(_) => IsolationLevel::Serializable,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::G0(_) => "G0",
+            Self::G1a { .. } => "G1a",
+            Self::G1b { .. } => "G1b",
+            Self::G1c(_) => "G1c",
+            Self::LostUpdate { .. } => "lost update",
+            Self::GSingle(_) => "G-single",
+            Self::G2(_) => "G2",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub txns: Vec<Txn>,
+    pub anomalies: Vec<Anomaly>,
+}
+
+impl Report {
+    /// The weakest isolation level the history violates
+    pub fn violated(&self) -> Option<IsolationLevel> {
+        self.anomalies.iter().map(Anomaly::violates).min()
+    }
+}
+
+/// How many cycles of each kind are reported
+const MAX_CYCLES: usize = 8;
+
+/// The transactions of the `txn` operations in a history
+///
+/// Micro operations are `["r", key, value]` and `["w", key, value]` arrays, the
+/// way maelstrom's txn workloads send them
+pub fn from_history(events: &[Event]) -> Result<Vec<Txn>, String> {
+    let parse = |body: &history::Body| -> Result<Vec<Mop>, String> {
+        let mops = body
+            .get("txn")
+            .and_then(Value::as_array)
+            .ok_or_else(|| format!("a txn without micro operations: {body:?}"))?;
+
+        mops.iter()
+            .map(|mop| {
+                let invalid = || format!("invalid micro operation {mop}");
+                let [f, key, value] = mop.as_array().map(Vec::as_slice).ok_or_else(invalid)? else {
+                    return Err(invalid());
+                };
+                let key = key.as_u64().ok_or_else(invalid)?;
+
+                match f.as_str() {
+                    Some("r") => Ok(Mop::Read {
+                        key,
+                        value: value.as_u64(),
+                    }),
+                    Some("w") => Ok(Mop::Write {
+                        key,
+                        value: value.as_u64().ok_or_else(invalid)?,
+                    }),
+                    _ => Err(invalid()),
+                }
+            })
+            .collect()
+    };
+
+    history::operations(events)
+        .into_iter()
+        .filter(|operation| operation.ty() == "txn")
+        .map(|operation| {
+            let (status, mops) = match (operation.kind, &operation.reply) {
+                (EventKind::Ok, Some(reply)) => (Status::Committed, parse(reply)?),
+                (EventKind::Fail, _) => (Status::Aborted, parse(&operation.request)?),
+                _ => (Status::Unknown, parse(&operation.request)?),
+            };
+
+            // reads that did not complete say nothing
+            let mops = match status {
+                Status::Committed => mops,
+                _ => mops
+                    .into_iter()
+                    .map(|mop| match mop {
+                        Mop::Read { key, .. } => Mop::Read { key, value: None },
+                        write => write,
+                    })
+                    .collect(),
+            };
+
+            Ok(Txn {
+                process: operation.process,
+                mops,
+                status,
+            })
+        })
+        .collect()
+}
+
+/// The reads of a transaction that are not of its own writes
+fn external_reads(txn: &Txn) -> impl Iterator<Item = (u64, Option<u64>)> + '_ {
+    let mut written = HashSet::new();
+    let mut read = HashSet::new();
+
+    txn.mops.iter().filter_map(move |mop| match *mop {
+        Mop::Write { key, .. } => {
+            written.insert(key);
+            None
+        }
+        Mop::Read { key, value } => {
+            (!written.contains(&key) && read.insert(key)).then_some((key, value))
+        }
+    })
+}
+
+/// The last value a transaction wrote to each key
+fn final_writes(txn: &Txn) -> HashMap<u64, u64> {
+    txn.mops
+        .iter()
+        .filter_map(|mop| match *mop {
+            Mop::Write { key, value } => Some((key, value)),
+            Mop::Read { .. } => None,
+        })
+        .collect()
+}
+
+struct Graph {
+    edges: Vec<Vec<(usize, Dependency)>>,
+}
+
+impl Graph {
+    fn add(&mut self, from: usize, to: usize, dependency: Dependency) {
+        if from != to && !self.edges[from].contains(&(to, dependency)) {
+            self.edges[from].push((to, dependency));
+        }
+    }
+
+    fn all(&self, dependency: Dependency) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.edges
+            .iter()
+            .enumerate()
+            .flat_map(move |(from, edges)| {
+                edges
+                    .iter()
+                    .filter(move |&&(_, edge)| edge == dependency)
+                    .map(move |&(to, _)| (from, to))
+            })
+    }
+
+    /// The shortest path using only `allowed` edges
+    fn path(
+        &self,
+        from: usize,
+        to: usize,
+        allowed: &[Dependency],
+    ) -> Option<Vec<(usize, Dependency)>> {
+        let mut previous = HashMap::new();
+        let mut queue = VecDeque::from([from]);
+
+        while let Some(node) = queue.pop_front() {
+            if node == to {
+                let mut path = Vec::new();
+                let mut node = to;
+                while node != from {
+                    let (before, dependency) = previous[&node];
+                    path.push((before, dependency));
+                    node = before;
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            for &(next, dependency) in &self.edges[node] {
+                if allowed.contains(&dependency) && next != from && !previous.contains_key(&next) {
+                    previous.insert(next, (node, dependency));
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Cycles made of one `dependency` edge and a path back of `allowed` edges,
+    /// skipping the sets of transactions that are already in a `seen` cycle
+    fn cycles(
+        &self,
+        dependency: Dependency,
+        allowed: &[Dependency],
+        seen: &mut HashSet<Vec<usize>>,
+    ) -> Vec<Cycle> {
+        let mut cycles = Vec::new();
+
+        for (from, to) in self.all(dependency) {
+            let Some(back) = self.path(to, from, allowed) else {
+                continue;
+            };
+
+            let mut steps = vec![(from, dependency)];
+            steps.extend(back);
+
+            let mut txns: Vec<usize> = steps.iter().map(|&(txn, _)| txn).collect();
+            txns.sort_unstable();
+            if seen.insert(txns) {
+                cycles.push(Cycle { steps });
+            }
+            if cycles.len() == MAX_CYCLES {
+                break;
+            }
+        }
+
+        cycles
+    }
+}
+
+/// Builds the dependency graph of the transactions and finds the anomalies in it
+pub fn check(txns: Vec<Txn>) -> Result<Report, String> {
+    let mut writers = HashMap::new();
+    let mut aborted = HashMap::new();
+
+    for (index, txn) in txns.iter().enumerate() {
+        for mop in &txn.mops {
+            let Mop::Write { key, value } = *mop else {
+                continue;
+            };
+
+            let writes = match txn.status {
+                Status::Aborted => &mut aborted,
+                Status::Committed | Status::Unknown => &mut writers,
+            };
+            if let Some(other) = writes.insert((key, value), index) {
+                if other != index {
+                    return Err(format!(
+                        "{value} was written to {key} twice, every write must be unique"
+                    ));
+                }
+            }
+        }
+    }
+
+    let finals: Vec<HashMap<u64, u64>> = txns.iter().map(final_writes).collect();
+    let mut graph = Graph {
+        edges: vec![Vec::new(); txns.len()],
+    };
+    let mut anomalies = Vec::new();
+
+    // the transactions that overwrote each version, `None` being the initial one
+    let mut overwritten_by: HashMap<(u64, Option<u64>), Vec<usize>> = HashMap::new();
+
+    for (index, txn) in txns.iter().enumerate() {
+        if txn.status != Status::Committed {
+            continue;
+        }
+
+        for (key, value) in external_reads(txn) {
+            if let Some(value) = value {
+                if let Some(&writer) = aborted.get(&(key, value)) {
+                    anomalies.push(Anomaly::G1a {
+                        reader: index,
+                        writer,
+                        key,
+                        value,
+                    });
+                    continue;
+                }
+
+                let Some(&writer) = writers.get(&(key, value)) else {
+                    continue;
+                };
+
+                if finals[writer].get(&key) != Some(&value) {
+                    anomalies.push(Anomaly::G1b {
+                        reader: index,
+                        writer,
+                        key,
+                        value,
+                    });
+                } else {
+                    graph.add(writer, index, Dependency::Wr);
+                }
+            }
+
+            // blind writes are left out, the version they overwrote is not known
+            if finals[index].contains_key(&key) {
+                if let Some(&writer) = value.and_then(|value| writers.get(&(key, value))) {
+                    graph.add(writer, index, Dependency::Ww);
+                }
+                overwritten_by.entry((key, value)).or_default().push(index);
+            }
+        }
+    }
+
+    for (index, txn) in txns.iter().enumerate() {
+        if txn.status != Status::Committed {
+            continue;
+        }
+
+        for (key, value) in external_reads(txn) {
+            for &overwriter in overwritten_by.get(&(key, value)).into_iter().flatten() {
+                graph.add(index, overwriter, Dependency::Rw);
+            }
+        }
+    }
+
+    // the strongest anomaly is reported for cycles of the same transactions, the
+    // writers of a lost update also have `rw` edges to each other
+    let mut seen = HashSet::new();
+    let mut lost: Vec<_> = overwritten_by
+        .into_iter()
+        .filter(|(_, writers)| writers.len() > 1)
+        .collect();
+    lost.sort_unstable();
+    for ((key, value), writers) in lost {
+        let mut txns = writers.clone();
+        txns.sort_unstable();
+        seen.insert(txns);
+        anomalies.push(Anomaly::LostUpdate {
+            writers,
+            key,
+            value,
+        });
+    }
+
+    let (ww, wr, rw) = (Dependency::Ww, Dependency::Wr, Dependency::Rw);
+    let mut cycles =
+        |dependency, allowed: &[Dependency]| graph.cycles(dependency, allowed, &mut seen);
+
+    let g0 = cycles(ww, &[ww]);
+    let g1c = cycles(wr, &[ww, wr]);
+    let single = cycles(rw, &[ww, wr]);
+    let g2 = cycles(rw, &[ww, wr, rw]);
+
+    anomalies.extend(g0.into_iter().map(Anomaly::G0));
+    anomalies.extend(g1c.into_iter().map(Anomaly::G1c));
+    anomalies.extend(single.into_iter().map(Anomaly::GSingle));
+    anomalies.extend(g2.into_iter().map(Anomaly::G2));
+
+    Ok(Report { txns, anomalies })
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ww => write!(f, "ww"),
+            Self::Wr => write!(f, "wr"),
+            Self::Rw => write!(f, "rw"),
+        }
+    }
+}
+
+impl fmt::Display for IsolationLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadUncommitted => write!(f, "read uncommitted"),
+            Self::ReadCommitted => write!(f, "read committed"),
+            Self::SnapshotIsolation => write!(f, "snapshot isolation"),
+            Self::Serializable => write!(f, "serializable"),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let txn = |index: usize| {
+            let txn = &self.txns[index];
+            let mops: Vec<String> = txn
+                .mops
+                .iter()
+                .map(|mop| match *mop {
+                    Mop::Read {
+                        key,
+                        value: Some(value),
+                    } => format!("r {key} {value}"),
+                    Mop::Read { key, value: None } => format!("r {key} nil"),
+                    Mop::Write { key, value } => format!("w {key} {value}"),
+                })
+                .collect();
+            format!("T{index} (process {}) [{}]", txn.process, mops.join(", "))
+        };
+
+        writeln!(
+            f,
+            "txn: {} transactions, {} committed",
+            self.txns.len(),
+            self.txns
+                .iter()
+                .filter(|txn| txn.status == Status::Committed)
+                .count()
+        )?;
+
+        for anomaly in &self.anomalies {
+            write!(f, "{}: ", anomaly.name())?;
+            match anomaly {
+                Anomaly::G1a {
+                    reader,
+                    writer,
+                    key,
+                    value,
+                } => writeln!(
+                    f,
+                    "{} read {key} = {value} from the aborted {}",
+                    txn(*reader),
+                    txn(*writer)
+                )?,
+                Anomaly::G1b {
+                    reader,
+                    writer,
+                    key,
+                    value,
+                } => writeln!(
+                    f,
+                    "{} read {key} = {value}, which {} overwrote",
+                    txn(*reader),
+                    txn(*writer)
+                )?,
+                Anomaly::LostUpdate {
+                    writers,
+                    key,
+                    value,
+                } => {
+                    let value = value.map_or("nil".to_owned(), |value| value.to_string());
+                    writeln!(
+                        f,
+                        "{} transactions overwrote {key} = {value}",
+                        writers.len()
+                    )?;
+                    for &writer in writers {
+                        writeln!(f, "  {}", txn(writer))?;
+                    }
+                }
+                Anomaly::G0(cycle)
+                | Anomaly::G1c(cycle)
+                | Anomaly::GSingle(cycle)
+                | Anomaly::G2(cycle) => {
+                    writeln!(f, "a cycle of {} transactions", cycle.steps.len())?;
+                    for &(index, dependency) in &cycle.steps {
+                        writeln!(f, "  {} -{dependency}->", txn(index))?;
+                    }
+                    writeln!(f, "  {}", txn(cycle.steps[0].0))?;
+                }
+            }
+        }
+
+        match self.violated() {
+            Some(level) => writeln!(f, "violates {level}"),
+            None => writeln!(f, "valid"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn r(key: u64, value: impl Into<Option<u64>>) -> Mop {
+        Mop::Read {
+            key,
+            value: value.into(),
+        }
+    }
+
+    fn w(key: u64, value: u64) -> Mop {
+        Mop::Write { key, value }
+    }
+
+    fn committed(mops: &[Mop]) -> Txn {
+        Txn {
+            process: 0,
+            mops: mops.to_vec(),
+            status: Status::Committed,
+        }
+    }
+
+    fn anomalies(txns: Vec<Txn>) -> Vec<Anomaly> {
+        check(txns).unwrap().anomalies
+    }
+
+    fn cycle(steps: &[(usize, Dependency)]) -> Cycle {
+        Cycle {
+            steps: steps.to_vec(),
+        }
+    }
+
+    const X: u64 = 0;
+    const Y: u64 = 1;
+    const WW: Dependency = Dependency::Ww;
+    const WR: Dependency = Dependency::Wr;
+    const RW: Dependency = Dependency::Rw;
+
+    #[test]
+    fn serial_histories_are_valid() {
+        let report = check(vec![
+            committed(&[r(X, None), w(X, 1)]),
+            committed(&[r(X, 1), w(X, 2), w(Y, 1)]),
+            committed(&[r(X, 2), r(Y, 1)]),
+        ])
+        .unwrap();
+
+        assert_eq!(report.anomalies, []);
+        assert_eq!(report.violated(), None);
+        assert!(report.to_string().ends_with("valid\n"));
+    }
+
+    #[test]
+    fn g0() {
+        // each transaction overwrote the other's write on one of the keys
+        let txns = vec![
+            committed(&[r(X, None), w(X, 1), r(Y, 1), w(Y, 2)]),
+            committed(&[r(Y, None), w(Y, 1), r(X, 1), w(X, 2)]),
+        ];
+
+        let anomalies = anomalies(txns);
+        assert_eq!(anomalies, [Anomaly::G0(cycle(&[(0, WW), (1, WW)]))]);
+        assert_eq!(anomalies[0].violates(), IsolationLevel::ReadUncommitted);
+    }
+
+    #[test]
+    fn g1a() {
+        let txns = vec![
+            Txn {
+                process: 0,
+                mops: vec![w(X, 1)],
+                status: Status::Aborted,
+            },
+            committed(&[r(X, 1)]),
+        ];
+
+        assert_eq!(
+            anomalies(txns),
+            [Anomaly::G1a {
+                reader: 1,
+                writer: 0,
+                key: X,
+                value: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn g1b() {
+        let txns = vec![committed(&[w(X, 1), w(X, 2)]), committed(&[r(X, 1)])];
+
+        assert_eq!(
+            anomalies(txns),
+            [Anomaly::G1b {
+                reader: 1,
+                writer: 0,
+                key: X,
+                value: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn g1c() {
+        // each transaction read the other's write
+        let txns = vec![
+            committed(&[w(X, 1), r(Y, 1)]),
+            committed(&[w(Y, 1), r(X, 1)]),
+        ];
+
+        let anomalies = anomalies(txns);
+        assert_eq!(anomalies, [Anomaly::G1c(cycle(&[(0, WR), (1, WR)]))]);
+        assert_eq!(anomalies[0].violates(), IsolationLevel::ReadCommitted);
+    }
+
+    #[test]
+    fn g_single() {
+        // a read skew: the first transaction saw the write to y but not to x
+        let txns = vec![
+            committed(&[r(X, None), r(Y, 1)]),
+            committed(&[r(X, None), w(X, 1), w(Y, 1)]),
+        ];
+
+        let anomalies = anomalies(txns);
+        assert_eq!(anomalies, [Anomaly::GSingle(cycle(&[(0, RW), (1, WR)]))]);
+        assert_eq!(anomalies[0].violates(), IsolationLevel::SnapshotIsolation);
+    }
+
+    #[test]
+    fn g2() {
+        // a write skew: each transaction overwrote a key the other one read
+        let txns = vec![
+            committed(&[r(X, None), r(Y, None), w(X, 1)]),
+            committed(&[r(X, None), r(Y, None), w(Y, 1)]),
+        ];
+
+        let anomalies = anomalies(txns);
+        assert_eq!(anomalies, [Anomaly::G2(cycle(&[(0, RW), (1, RW)]))]);
+        assert_eq!(anomalies[0].violates(), IsolationLevel::Serializable);
+    }
+
+    #[test]
+    fn overwriters_of_the_same_version_lost_an_update() {
+        let txns = vec![
+            committed(&[r(X, None), w(X, 1)]),
+            committed(&[r(X, None), w(X, 2)]),
+            // the order the writes were installed in is not known from this read
+            committed(&[r(X, 2)]),
+        ];
+
+        let report = check(txns).unwrap();
+        assert_eq!(
+            report.anomalies,
+            [Anomaly::LostUpdate {
+                writers: vec![0, 1],
+                key: X,
+                value: None
+            }]
+        );
+        assert_eq!(report.violated(), Some(IsolationLevel::SnapshotIsolation));
+        assert!(report.to_string().contains("lost update: 2 transactions"));
+    }
+
+    #[test]
+    fn blind_writes_have_no_ww_edges() {
+        // G0, but neither transaction read what it overwrote
+        let txns = vec![
+            committed(&[w(X, 1), w(Y, 2)]),
+            committed(&[w(Y, 1), w(X, 2)]),
+        ];
+
+        assert_eq!(anomalies(txns), []);
+    }
+
+    #[test]
+    fn values_must_be_unique() {
+        let txns = vec![committed(&[w(X, 1)]), committed(&[w(X, 1)])];
+
+        assert!(check(txns).is_err());
+    }
+}
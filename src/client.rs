@@ -1,39 +1,132 @@
 use std::{
     borrow::Borrow,
-    io::{BufRead, BufReader, BufWriter, Stdin, Stdout, Write},
+    collections::{HashMap, VecDeque},
+    io::{BufRead, BufReader, Write},
+    sync::{
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::{Error, Message, NodeId, Response};
+use crate::{
+    clock::{Clock, ClockInterceptor, ClockKind, Timestamp},
+    cluster::Cluster,
+    fault::{FaultConfig, FaultInjector},
+    flush::{FlushPolicy, Output},
+    interceptor::{Chain, Flow, Frame, Interceptor, StderrLogger},
+    metrics::Metrics,
+    panic::{self, PanicPolicy},
+    request::{Kind, Requests},
+    rpc::{self, Failure, Gathered, Rpc, Typed},
+    shutdown::Shutdown,
+    transcript::TranscriptRecorder,
+    wire::{self, Encoder},
+    Error, ErrorCode, ErrorPayload, Message, NodeId, Response,
+};
 
 #[derive()]
 pub struct MaelstromClient {
     node_id: NodeId,
     node_ids: Vec<NodeId>,
+    cluster: Cluster,
 
     msg_id: Option<u32>,
-    stdin: BufReader<Stdin>,
-    stdout: BufWriter<Stdout>,
+    input: Input,
+    output: Arc<Mutex<Output>>,
     buf: Vec<u8>,
+    encoder: Encoder,
+    /// The frames of the last write, kept to reuse their buffers
+    frames: Vec<Frame>,
+    interceptors: Arc<Mutex<Chain>>,
+    clock: Option<Arc<Mutex<Clock>>>,
+    metrics: Metrics,
+    panic_policy: PanicPolicy,
+    shutdown: Shutdown,
+    requests: Requests,
+    /// Replies to the requests of the current call, by the `msg_id` of the request
+    replies: HashMap<u32, serde_json::Map<String, serde_json::Value>>,
+    /// Frames for the node that were read during a call
+    deferred: VecDeque<Vec<u8>>,
+}
+
+enum Input {
+    Reader(Box<dyn BufRead + Send + Sync>),
+    /// Lines read by a background thread, see [`MaelstromClient::spawn_reader`],
+    /// `None` once the shutdown is triggered
+    Lines(Mutex<Receiver<Option<std::io::Result<Vec<u8>>>>>),
+    Detached,
+}
+
+pub(crate) enum NextFrame {
+    Frame(Vec<u8>),
+    Timeout,
+    Eof,
+}
+
+/// A frame that could not be deserialized
+struct Rejection {
+    /// The sender and the `msg_id` of the message, if it should get the error
+    reply_to: Option<(NodeId, u32)>,
+    error: ErrorPayload,
+}
+
+pub(crate) enum Incoming {
+    /// The message is in `MaelstromClient::buf`
+    Message,
+    Timeout,
+    Eof,
 }
 
 impl MaelstromClient {
     pub fn new() -> Result<Self, Error> {
-        let mut client = Self {
-            node_id: NodeId::seq_kv(),
-            node_ids: Vec::new(),
-            msg_id: Some(0),
-            stdin: BufReader::new(std::io::stdin()),
-            stdout: BufWriter::new(std::io::stdout()),
-            buf: Vec::new(),
-        };
+        let mut client = Self::with_io(BufReader::new(std::io::stdin()), std::io::stdout());
+        client.shutdown.on_signals()?;
+        client.spawn_reader();
+
+        client.add_interceptor(StderrLogger::new());
+        if let Some(recorder) = TranscriptRecorder::from_env() {
+            client.add_interceptor(recorder);
+        }
 
         client.handle_init()?;
 
+        client.add_interceptor(client.metrics.clone());
+        if let Some(config) = FaultConfig::from_env()? {
+            client.add_interceptor(FaultInjector::new(config));
+        }
+
         Ok(client)
     }
 
+    /// A client that has not done the init handshake yet, and has no interceptors
+    pub(crate) fn with_io(
+        input: impl BufRead + Send + Sync + 'static,
+        output: impl Write + Send + 'static,
+    ) -> Self {
+        Self {
+            node_id: NodeId::seq_kv(),
+            node_ids: Vec::new(),
+            cluster: Cluster::new(NodeId::seq_kv(), []),
+            msg_id: Some(0),
+            input: Input::Reader(Box::new(input)),
+            output: Arc::new(Mutex::new(Output::new(Box::new(output)))),
+            buf: Vec::new(),
+            encoder: Encoder::new(),
+            frames: Vec::new(),
+            interceptors: Arc::default(),
+            clock: None,
+            metrics: Metrics::new(),
+            panic_policy: PanicPolicy::default(),
+            shutdown: Shutdown::new(),
+            requests: Requests::default(),
+            replies: HashMap::new(),
+            deferred: VecDeque::new(),
+        }
+    }
+
     pub fn node_id(&self) -> NodeId {
         self.node_id
     }
@@ -42,6 +135,11 @@ impl MaelstromClient {
         &self.node_ids
     }
 
+    /// The nodes from the init message, see [`crate::cluster`]
+    pub fn cluster(&self) -> &Cluster {
+        &self.cluster
+    }
+
     pub fn message_id(&self) -> Option<u32> {
         self.msg_id
     }
@@ -51,35 +149,675 @@ impl MaelstromClient {
         MaelstromClient {
             node_id: self.node_id,
             node_ids: self.node_ids.clone(),
+            cluster: self.cluster.clone(),
             msg_id: None,
-            stdin: BufReader::new(std::io::stdin()),
-            stdout: BufWriter::new(std::io::stdout()),
+            input: Input::Detached,
+            output: self.output.clone(),
             buf: Vec::new(),
+            encoder: Encoder::new(),
+            frames: Vec::new(),
+            interceptors: self.interceptors.clone(),
+            clock: self.clock.clone(),
+            metrics: self.metrics.clone(),
+            panic_policy: self.panic_policy,
+            shutdown: self.shutdown.clone(),
+            requests: Requests::default(),
+            replies: HashMap::new(),
+            deferred: VecDeque::new(),
+        }
+    }
+
+    /// Adds an interceptor closest to the wire, see [`crate::interceptor`] for the ordering
+    ///
+    /// Interceptors are shared between a client and all clients detached from it
+    pub fn add_interceptor(&mut self, interceptor: impl Interceptor + 'static) {
+        self.interceptors
+            .lock()
+            .unwrap()
+            .push(Box::new(interceptor));
+    }
+
+    /// Removes all interceptors, including the default [`StderrLogger`]
+    pub fn clear_interceptors(&mut self) {
+        self.interceptors.lock().unwrap().clear();
+    }
+
+    /// Records every frame from now on to `<dir>/<node id>.jsonl`, see [`crate::transcript`]
+    pub fn record_transcript(&mut self, dir: impl Into<std::path::PathBuf>) {
+        self.add_interceptor(TranscriptRecorder::new(dir));
+    }
+
+    /// Installs a [`ClockInterceptor`], which stamps every body sent to another node
+    /// with a `clock` field, and advances the clock with the `clock` field of every incoming body
+    ///
+    /// Unlike [`MaelstromClient::add_interceptor`] this installs it closest to the
+    /// application, so that the other interceptors, like the transcript recorder,
+    /// see the stamped bodies
+    pub fn enable_clock(&mut self, kind: ClockKind) {
+        let clock = Arc::new(Mutex::new(Clock::new(kind)));
+        self.interceptors
+            .lock()
+            .unwrap()
+            .push_front(Box::new(ClockInterceptor::new(self.node_id, clock.clone())));
+        self.clock = Some(clock);
+    }
+
+    /// The current time of the clock enabled with [`MaelstromClient::enable_clock`]
+    pub fn clock(&self) -> Option<Timestamp> {
+        let clock = self.clock.as_ref()?;
+        let now = clock.lock().unwrap().now();
+        Some(now)
+    }
+
+    /// The counters of the [`Metrics`] interceptor every client starts out with
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Writes a summary of the metrics to stderr, this also happens when stdin is closed
+    pub fn report_metrics(&self) -> Result<(), Error> {
+        self.metrics.report(self.node_id)
+    }
+
+    /// When the lines written by this client and all clients detached from it
+    /// are flushed, see [`crate::flush`]
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) -> Result<(), Error> {
+        let output = &mut *self.output.lock().unwrap();
+        output.set_policy(policy)?;
+
+        if !matches!(policy, FlushPolicy::Batched { .. }) || output.flusher {
+            return Ok(());
         }
+
+        output.flusher = true;
+        let flusher = self.output.clone();
+        self.spawn(move |_, shutdown| loop {
+            let max_delay = {
+                let output = &mut *flusher.lock().unwrap();
+                let FlushPolicy::Batched { max_delay, .. } = output.policy() else {
+                    output.flusher = false;
+                    return Ok(());
+                };
+                output.flush()?;
+                max_delay
+            };
+
+            if shutdown.wait_timeout(max_delay) {
+                return Ok(());
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Writes out the lines that the [`FlushPolicy`] is holding back
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.output.lock().unwrap().flush()?;
+        Ok(())
+    }
+
+    /// Triggered when stdin is closed or the process gets `SIGINT` or `SIGTERM`,
+    /// see [`crate::shutdown`]
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    /// Runs a background task on its own thread, with a detached client
+    ///
+    /// The task should return once the shutdown is triggered, the client waits
+    /// for it before it stops reading
+    pub fn spawn(
+        &self,
+        task: impl FnOnce(MaelstromClient, Shutdown) -> Result<(), Error> + Send + 'static,
+    ) {
+        let client = self.detach();
+        let shutdown = self.shutdown.clone();
+
+        self.shutdown
+            .track(std::thread::spawn(move || task(client, shutdown)));
+    }
+
+    /// Triggers the shutdown, waits for the background tasks, flushes stdout and
+    /// reports the metrics
+    pub(crate) fn shut_down(&mut self) -> Result<(), Error> {
+        self.shutdown.trigger();
+        self.shutdown.join();
+        self.poll_interceptors()?;
+        self.output.lock().unwrap().flush()?;
+
+        self.report_metrics()
+    }
+
+    /// Relays a request to `to`, and relays its reply back to the original sender
+    ///
+    /// If `to` does not reply within `timeout` the sender gets a `timeout` (0) error,
+    /// and if there is no node to forward to it gets a `temporarily-unavailable` (11) one
+    pub fn forward<T: Serialize>(
+        &mut self,
+        to: Option<NodeId>,
+        message: &Message<T>,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        if self.msg_id.is_none() {
+            return Err(Error::DetachedClientCantRead);
+        }
+
+        let Some(to) = to else {
+            return self.write_no_response(message.error_response(
+                ErrorCode::TEMPORARILY_UNAVAILABLE,
+                "there is no node to forward the request to",
+            ));
+        };
+
+        let request = Response {
+            dest: to,
+            in_reply_to: None,
+            payload: &message.payload,
+        };
+
+        let Some(in_reply_to) = message.msg_id else {
+            return self.write_no_response(request);
+        };
+
+        let msg_id = self.write(request)?;
+        self.requests.insert(
+            msg_id,
+            Instant::now() + timeout,
+            Kind::Forward {
+                requester: message.src,
+                in_reply_to,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Sends `request` to every node in `dests`, and waits until all of them replied
+    /// or `timeout` passed, see [`crate::rpc`]
+    pub fn call_all<R: DeserializeOwned>(
+        &mut self,
+        dests: &[NodeId],
+        request: &impl Serialize,
+        timeout: Duration,
+    ) -> Result<Gathered<R>, Error> {
+        self.call_quorum(dests, request, dests.len(), timeout)
+    }
+
+    /// Sends `request` to every node in `dests`, and waits until `k` of them replied
+    /// successfully, until so many failed that `k` is out of reach, or until `timeout` passed
+    ///
+    /// Fails without sending anything if there are fewer than `k` nodes in `dests`
+    pub fn call_quorum<R: DeserializeOwned>(
+        &mut self,
+        dests: &[NodeId],
+        request: &impl Serialize,
+        k: usize,
+        timeout: Duration,
+    ) -> Result<Gathered<R>, Error> {
+        if self.msg_id.is_none() {
+            return Err(Error::DetachedClientCantRead);
+        }
+
+        if k > dests.len() {
+            return Err(Error::ImpossibleQuorum(format!(
+                "{k} replies were needed from {} nodes",
+                dests.len()
+            )));
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut outstanding = HashMap::new();
+        for &dest in dests {
+            let msg_id = self.write(Response {
+                dest,
+                in_reply_to: None,
+                payload: request,
+            })?;
+
+            self.requests.insert(msg_id, deadline, Kind::Call);
+            outstanding.insert(msg_id, dest);
+        }
+
+        let mut gathered = Gathered {
+            replies: Vec::new(),
+            failures: Vec::new(),
+        };
+
+        loop {
+            for (msg_id, body) in self.replies.drain() {
+                let Some(dest) = outstanding.remove(&msg_id) else {
+                    continue;
+                };
+
+                match rpc::parse_reply(body) {
+                    Ok(reply) => gathered.replies.push((dest, reply)),
+                    Err(failure) => gathered.failures.push((dest, failure)),
+                }
+            }
+
+            if gathered.replies.len() + outstanding.len() < k
+                || gathered.replies.len() >= k
+                || outstanding.is_empty()
+            {
+                break;
+            }
+
+            match self.next_frame(self.requests.next_deadline())? {
+                NextFrame::Frame(frame) => {
+                    if self.accept(frame)? {
+                        self.deferred.push_back(std::mem::take(&mut self.buf));
+                    }
+                }
+                NextFrame::Timeout => {
+                    self.expire_requests()?;
+                    if deadline <= Instant::now() {
+                        break;
+                    }
+                }
+                NextFrame::Eof => {
+                    self.shutdown.trigger();
+                    break;
+                }
+            }
+        }
+
+        let timed_out = deadline <= Instant::now();
+        for (msg_id, dest) in outstanding {
+            self.requests.take(msg_id);
+
+            if timed_out {
+                gathered.failures.push((dest, Failure::Timeout));
+            } else {
+                self.requests.insert(msg_id, deadline, Kind::Late);
+            }
+        }
+
+        Ok(gathered)
+    }
+
+    /// Sends `request` to every node in `dests`, and waits until one of them replied
+    /// successfully, all of them failed, or `timeout` passed
+    pub fn call_any<R: DeserializeOwned>(
+        &mut self,
+        dests: &[NodeId],
+        request: &impl Serialize,
+        timeout: Duration,
+    ) -> Result<Gathered<R>, Error> {
+        self.call_quorum(dests, request, 1, timeout)
+    }
+
+    /// Sends `request` to `dest` and waits for its reply, see [`crate::rpc`]
+    pub fn call<R: Rpc>(
+        &mut self,
+        dest: NodeId,
+        request: &R,
+        timeout: Duration,
+    ) -> Result<Result<R::Reply, Failure>, Error> {
+        let mut gathered = self.call_any::<Typed<R::Reply>>(&[dest], &Typed(request), timeout)?;
+
+        Ok(match (gathered.replies.pop(), gathered.failures.pop()) {
+            (Some((_, Typed(reply))), _) => Ok(reply),
+            (None, Some((_, failure))) => Err(failure),
+            (None, None) => Err(Failure::Timeout),
+        })
+    }
+
+    /// Whether [`MaelstromClient::catch_panic`] keeps going after a handler panicked,
+    /// it does by default
+    pub fn set_panic_policy(&mut self, policy: PanicPolicy) {
+        self.panic_policy = policy;
+    }
+
+    /// Runs the handler of a message from `src`, replying with a `crash` (13)
+    /// error if it panics and the message has a `msg_id`
+    ///
+    /// Returns `None` if the handler panicked, unless the [`PanicPolicy`] is to exit
+    ///
+    /// A handler that panics while holding a lock poisons it, handlers that share
+    /// state should recover it with [`std::sync::PoisonError::into_inner`]
+    pub fn catch_panic<T>(
+        &mut self,
+        src: NodeId,
+        msg_id: Option<u32>,
+        handler: impl FnOnce(&mut Self) -> Result<T, Error>,
+    ) -> Result<Option<T>, Error> {
+        let payload = match panic::catch(|| handler(self)) {
+            Ok(result) => return result.map(Some),
+            Err(payload) => payload,
+        };
+
+        if let Some(msg_id) = msg_id {
+            let text = format!("the handler panicked: {}", panic::message(&*payload));
+            self.write_no_response(Response {
+                dest: src,
+                in_reply_to: Some(msg_id),
+                payload: ErrorPayload::new(ErrorCode::CRASH, text),
+            })?;
+        }
+
+        match self.panic_policy {
+            PanicPolicy::Continue => Ok(None),
+            PanicPolicy::Exit => std::panic::resume_unwind(payload),
+        }
+    }
+
+    /// The next message for the node, or `None` once stdin is closed or the
+    /// shutdown is triggered
+    ///
+    /// Messages that cannot be deserialized are logged and skipped, and get an
+    /// error reply if they have a `msg_id`
+    pub fn read<T: DeserializeOwned>(&mut self) -> Result<Option<Message<T>>, Error> {
+        loop {
+            if let Incoming::Eof = self.next_incoming(None)? {
+                self.shut_down()?;
+                return Ok(None);
+            }
+
+            if let Some(message) = self.incoming()? {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    /// Waits for the next frame for the node and leaves it in `self.buf`, handling
+    /// replies to tracked requests on the way
+    pub(crate) fn next_incoming(&mut self, deadline: Option<Instant>) -> Result<Incoming, Error> {
+        loop {
+            if let Some(frame) = self.deferred.pop_front() {
+                self.buf = frame;
+                return Ok(Incoming::Message);
+            }
+
+            let next = deadline
+                .into_iter()
+                .chain(self.requests.next_deadline())
+                .min();
+            match self.next_frame(next)? {
+                NextFrame::Frame(frame) => {
+                    if self.accept(frame)? {
+                        return Ok(Incoming::Message);
+                    }
+                }
+                NextFrame::Timeout => {
+                    self.expire_requests()?;
+                    if deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+                        return Ok(Incoming::Timeout);
+                    }
+                }
+                NextFrame::Eof => return Ok(Incoming::Eof),
+            }
+        }
+    }
+
+    /// Moves reading stdin to a background thread, so that [`MaelstromClient::next_frame`]
+    /// can time out
+    pub(crate) fn spawn_reader(&mut self) {
+        let Input::Reader(_) = self.input else {
+            return;
+        };
+
+        let Input::Reader(mut reader) = std::mem::replace(&mut self.input, Input::Detached) else {
+            unreachable!()
+        };
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        let shutdown = sender.clone();
+        self.shutdown.on_trigger(move || {
+            let _ = shutdown.send(None);
+        });
+
+        std::thread::spawn(move || loop {
+            let mut line = Vec::new();
+            let line = match reader.read_until(b'\n', &mut line) {
+                Ok(0) => None,
+                Ok(_) => Some(Ok(line)),
+                Err(err) => Some(Err(err)),
+            };
+
+            let done = !matches!(line, Some(Ok(_)));
+            if sender.send(line).is_err() || done {
+                break;
+            }
+        });
+
+        self.input = Input::Lines(Mutex::new(receiver));
     }
 
-    pub fn read<'de, T: Deserialize<'de>>(&'de mut self) -> Result<Option<Message<T>>, Error> {
+    /// The next raw frame, without the trailing newline
+    ///
+    /// The deadline is only respected after [`MaelstromClient::spawn_reader`]
+    pub(crate) fn next_frame(&mut self, deadline: Option<Instant>) -> Result<NextFrame, Error> {
         if self.msg_id.is_none() {
             return Err(Error::DetachedClientCantRead);
         }
 
-        self.buf.clear();
+        if self.shutdown.is_triggered() {
+            return Ok(NextFrame::Eof);
+        }
+
+        self.output.lock().unwrap().waiting()?;
+
+        let line = match &mut self.input {
+            Input::Reader(reader) => {
+                let mut line = std::mem::take(&mut self.buf);
+                line.clear();
 
-        let len = match self.stdin.read_until(b'\n', &mut self.buf) {
-            Ok(len) => len,
-            Err(err) => {
-                dbg!(&err);
-                return Err(err.into());
+                if reader.read_until(b'\n', &mut line)? == 0 {
+                    return Ok(NextFrame::Eof);
+                }
+                line
             }
+            Input::Lines(receiver) => {
+                let receiver = receiver.get_mut().unwrap();
+                let line = match deadline {
+                    Some(deadline) => {
+                        match receiver
+                            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                        {
+                            Ok(line) => line,
+                            Err(RecvTimeoutError::Timeout) => return Ok(NextFrame::Timeout),
+                            Err(RecvTimeoutError::Disconnected) => return Ok(NextFrame::Eof),
+                        }
+                    }
+                    None => match receiver.recv() {
+                        Ok(line) => line,
+                        Err(_) => return Ok(NextFrame::Eof),
+                    },
+                };
+
+                match line {
+                    Some(line) => line?,
+                    None => return Ok(NextFrame::Eof),
+                }
+            }
+            Input::Detached => return Err(Error::DetachedClientCantRead),
         };
-        if len == 0 {
+
+        let mut line = line;
+        if line.ends_with(b"\n") {
+            line.pop();
+        }
+
+        Ok(NextFrame::Frame(line))
+    }
+
+    /// Runs a raw frame through the interceptors, leaving it in `self.buf`
+    fn intercept_read(&mut self, frame: Vec<u8>) -> Result<Flow, Error> {
+        let mut frame = Frame::new(frame);
+        let flow = self.interceptors.lock().unwrap().on_read(&mut frame);
+        self.buf = frame.into_bytes();
+        flow
+    }
+
+    /// Deserializes a raw frame, after running it through the interceptors
+    pub(crate) fn receive<T: DeserializeOwned>(
+        &mut self,
+        frame: Vec<u8>,
+    ) -> Result<Option<Message<T>>, Error> {
+        if !self.accept(frame)? {
             return Ok(None);
         }
-        let buf = bstr::BStr::new(&self.buf[..len]);
-        eprintln!("Read from stdin:");
-        eprintln!("{buf}");
-        let value = serde_json::from_slice(&self.buf[..len])?;
-        Ok(Some(value))
+
+        self.incoming()
+    }
+
+    /// Deserializes the frame left in `self.buf` by [`MaelstromClient::next_incoming`]
+    pub(crate) fn incoming<T: DeserializeOwned>(&mut self) -> Result<Option<Message<T>>, Error> {
+        let buf = std::mem::take(&mut self.buf);
+        let message = self.deserialize(&buf);
+        self.buf = buf;
+
+        message
+    }
+
+    /// Hands the lines read from stdin over to the async client, after which this
+    /// client can only write
+    #[cfg(feature = "async")]
+    pub(crate) fn take_lines(&mut self) -> Receiver<Option<std::io::Result<Vec<u8>>>> {
+        self.spawn_reader();
+
+        match std::mem::replace(&mut self.input, Input::Detached) {
+            Input::Lines(receiver) => receiver.into_inner().unwrap(),
+            _ => unreachable!("the reader was just spawned"),
+        }
+    }
+
+    /// Like [`MaelstromClient::accept`], but returns the frame if it is for the node
+    #[cfg(feature = "async")]
+    pub(crate) fn accept_owned(&mut self, frame: Vec<u8>) -> Result<Option<Vec<u8>>, Error> {
+        if self.accept(frame)? {
+            Ok(Some(std::mem::take(&mut self.buf)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Runs a raw frame through the interceptors and handles replies to the requests
+    /// the client tracks, returns whether the frame in `self.buf` is for the node
+    fn accept(&mut self, frame: Vec<u8>) -> Result<bool, Error> {
+        #[derive(Deserialize)]
+        struct Reply {
+            body: serde_json::Map<String, serde_json::Value>,
+        }
+
+        if self.intercept_read(frame)? == Flow::Drop {
+            return Ok(false);
+        }
+
+        if self.requests.is_empty() {
+            return Ok(true);
+        }
+
+        let Some(msg_id) = wire::header(&self.buf)
+            .ok()
+            .and_then(|header| header.body.in_reply_to)
+        else {
+            return Ok(true);
+        };
+        let Some(kind) = self.requests.take(msg_id) else {
+            return Ok(true);
+        };
+        let Reply { mut body } = serde_json::from_slice(&self.buf)?;
+
+        match kind {
+            Kind::Forward {
+                requester,
+                in_reply_to,
+            } => {
+                body.remove("msg_id");
+                body.remove("in_reply_to");
+
+                self.write_no_response(Response {
+                    dest: requester,
+                    in_reply_to: Some(in_reply_to),
+                    payload: body,
+                })?;
+            }
+            Kind::Call => {
+                self.replies.insert(msg_id, body);
+            }
+            Kind::Late => (),
+        }
+
+        Ok(false)
+    }
+
+    /// Fails the tracked requests whose deadline passed
+    fn expire_requests(&mut self) -> Result<(), Error> {
+        for kind in self.requests.expire(Instant::now()) {
+            match kind {
+                Kind::Forward {
+                    requester,
+                    in_reply_to,
+                } => self.write_no_response(Response {
+                    dest: requester,
+                    in_reply_to: Some(in_reply_to),
+                    payload: ErrorPayload::new(
+                        ErrorCode::TIMEOUT,
+                        "the forwarded request was not answered in time",
+                    ),
+                })?,
+                Kind::Call | Kind::Late => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deserializes a frame, or rejects it if it has an unknown type or is malformed
+    pub(crate) fn deserialize<T: DeserializeOwned>(
+        &mut self,
+        frame: &[u8],
+    ) -> Result<Option<Message<T>>, Error> {
+        match Self::parse(frame) {
+            Ok(message) => Ok(Some(message)),
+            Err(rejection) => self.reject(rejection).map(|()| None),
+        }
+    }
+
+    /// Deserializes a frame, logging it if it is rejected
+    fn parse<'de, T: Deserialize<'de>>(frame: &'de [u8]) -> Result<Message<T>, Rejection> {
+        let err = match serde_json::from_slice(frame) {
+            Ok(message) => return Ok(message),
+            Err(err) => err,
+        };
+
+        eprintln!("Rejected message: {err}");
+        eprintln!("{}", bstr::BStr::new(frame));
+
+        let header = wire::header(frame).ok();
+        let ty = header.as_ref().and_then(|header| header.body.ty.as_deref());
+
+        let known = wire::known_types::<T>();
+        let error = match ty {
+            Some(ty) if !known.is_empty() && !known.contains(&ty) => ErrorPayload::new(
+                ErrorCode::NOT_SUPPORTED,
+                format!("unknown message type `{ty}`"),
+            ),
+            _ => ErrorPayload::new(ErrorCode::MALFORMED_REQUEST, err.to_string()),
+        };
+
+        // replying to errors or replies could bounce between two nodes forever
+        let reply_to = header
+            .as_ref()
+            .filter(|header| header.body.in_reply_to.is_none() && ty != Some("error"))
+            .and_then(|header| Some((header.src, header.body.msg_id?)));
+
+        Err(Rejection { reply_to, error })
+    }
+
+    /// Replies to a rejected message, if it has a `msg_id`
+    fn reject(&mut self, rejection: Rejection) -> Result<(), Error> {
+        let Some((src, msg_id)) = rejection.reply_to else {
+            return Ok(());
+        };
+
+        self.write_no_response(Response {
+            dest: src,
+            in_reply_to: Some(msg_id),
+            payload: rejection.error,
+        })
     }
 
     fn write_<T: Serialize>(
@@ -88,9 +826,22 @@ impl MaelstromClient {
         needs_response: bool,
     ) -> Result<Option<u32>, Error> {
         let msg_id = if needs_response { self.msg_id } else { None };
-        serde_json::to_writer(&mut self.stdout, &resp.raw(self.node_id, msg_id))?;
-        self.stdout.write_all(b"\n")?;
-        self.stdout.flush()?;
+
+        let mut frames = std::mem::take(&mut self.frames);
+        let mut bytes = frames.pop().map(Frame::into_bytes).unwrap_or_default();
+        frames.clear();
+
+        bytes.clear();
+        self.encoder.encode(
+            &mut bytes,
+            self.node_id,
+            resp.dest,
+            msg_id,
+            resp.in_reply_to,
+            &resp.payload,
+        )?;
+        frames.push(Frame::new(bytes));
+        self.write_frames(frames)?;
 
         if let Some(ref mut msg_id) = self.msg_id {
             *msg_id += 1;
@@ -99,6 +850,33 @@ impl MaelstromClient {
         Ok(msg_id)
     }
 
+    fn write_frames(&mut self, mut frames: Vec<Frame>) -> Result<(), Error> {
+        self.interceptors.lock().unwrap().on_write(&mut frames)?;
+
+        let output = &mut *self.output.lock().unwrap();
+        for frame in &frames {
+            output.write_line(frame.as_bytes())?;
+        }
+        output.written()?;
+
+        self.frames = frames;
+
+        Ok(())
+    }
+
+    /// Tells the interceptors that a timer fired, and writes any frames they release
+    pub fn notify_tick(&mut self) -> Result<(), Error> {
+        self.interceptors.lock().unwrap().on_tick()?;
+        self.poll_interceptors()
+    }
+
+    /// Writes any frames that interceptors are holding back and are now due
+    ///
+    /// This already happens on every write, but idle nodes should call this periodically
+    pub fn poll_interceptors(&mut self) -> Result<(), Error> {
+        self.write_frames(Vec::new())
+    }
+
     pub fn write<T: Serialize>(&mut self, resp: impl Borrow<Response<T>>) -> Result<u32, Error> {
         self.write_(resp.borrow(), true).map(Option::unwrap)
     }
@@ -110,7 +888,7 @@ impl MaelstromClient {
         self.write_(resp.borrow(), false).map(drop)
     }
 
-    fn handle_init(&mut self) -> Result<(), Error> {
+    pub(crate) fn handle_init(&mut self) -> Result<(), Error> {
         #[derive(Deserialize)]
         #[serde(tag = "type", rename_all = "lowercase")]
         pub enum InitPayload {
@@ -127,6 +905,7 @@ impl MaelstromClient {
 
         let InitPayload::Init { node_id, node_ids } = init.payload;
         self.node_id = node_id;
+        self.cluster = Cluster::new(node_id, node_ids.iter().copied());
         self.node_ids = node_ids;
 
         self.write(resp)?;
@@ -145,3 +924,231 @@ const _: () = {
     assert_send_sync::<Message<()>>;
     assert_send_sync::<Response<()>>;
 };
+
+#[cfg(test)]
+mod tests {
+    use std::panic::AssertUnwindSafe;
+
+    use super::*;
+    use crate::{node, raft, testing, workloads::lin_kv::KvPayload, Node};
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "snake_case", tag = "type")]
+    enum Payload {
+        Add {
+            #[allow(dead_code)]
+            delta: u32,
+        },
+        Read,
+    }
+
+    fn reject<T: DeserializeOwned>(frame: &str) -> Rejection {
+        match MaelstromClient::parse::<T>(frame.as_bytes()) {
+            Ok(_) => panic!("{frame} was accepted"),
+            Err(rejection) => rejection,
+        }
+    }
+
+    #[test]
+    fn unknown_types_are_not_supported() {
+        let rejection =
+            reject::<Payload>(r#"{"src":"c1","dest":"n1","body":{"type":"sub","msg_id":3}}"#);
+        assert_eq!(rejection.reply_to, Some((NodeId::client(1), 3)));
+        assert_eq!(rejection.error.code, ErrorCode::NOT_SUPPORTED);
+
+        // untagged payloads only say that no variant matched
+        let rejection = reject::<raft::Payload<KvPayload>>(
+            r#"{"src":"c1","dest":"n1","body":{"type":"delete","msg_id":3,"key":1}}"#,
+        );
+        assert_eq!(rejection.error.code, ErrorCode::NOT_SUPPORTED);
+    }
+
+    #[test]
+    fn known_types_with_invalid_fields_are_malformed() {
+        let rejection = reject::<Payload>(
+            r#"{"src":"c1","dest":"n1","body":{"type":"add","msg_id":3,"delta":"one"}}"#,
+        );
+        assert_eq!(rejection.error.code, ErrorCode::MALFORMED_REQUEST);
+
+        let rejection = reject::<raft::Payload<KvPayload>>(
+            r#"{"src":"c1","dest":"n1","body":{"type":"read","msg_id":3}}"#,
+        );
+        assert_eq!(rejection.error.code, ErrorCode::MALFORMED_REQUEST);
+
+        let rejection = reject::<Payload>(r#"{"src":"c1","dest":"n1","body":{"msg_id":3}}"#);
+        assert_eq!(rejection.error.code, ErrorCode::MALFORMED_REQUEST);
+    }
+
+    #[test]
+    fn replies_and_errors_are_not_answered() {
+        for body in [
+            r#"{"type":"sub"}"#,
+            r#"{"type":"sub","msg_id":3,"in_reply_to":1}"#,
+            r#"{"type":"error","msg_id":3,"code":1}"#,
+        ] {
+            let frame = format!(r#"{{"src":"n2","dest":"n1","body":{body}}}"#);
+            assert_eq!(reject::<Payload>(&frame).reply_to, None, "{body}");
+        }
+
+        assert_eq!(reject::<Payload>("not json").reply_to, None);
+    }
+
+    #[test]
+    fn impossible_quorums_fail_without_sending() {
+        let mut client = MaelstromClient::with_io(std::io::empty(), std::io::sink());
+        let dests = [NodeId::node(1), NodeId::node(2)];
+        let request = serde_json::json!({"type": "read"});
+
+        let gathered =
+            client.call_quorum::<serde_json::Value>(&dests, &request, 3, Duration::from_millis(10));
+        assert!(matches!(gathered, Err(Error::ImpossibleQuorum(_))));
+        assert_eq!(client.message_id(), Some(0));
+    }
+
+    /// Panics on `boom`, and replies to `add`
+    struct Fragile;
+
+    impl Node for Fragile {
+        type Payload = serde_json::Value;
+
+        fn handle(
+            &mut self,
+            client: &mut MaelstromClient,
+            message: Message<Self::Payload>,
+        ) -> Result<(), Error> {
+            if message.payload["type"] == "boom" {
+                panic!("boom");
+            }
+            client.write(message.response(serde_json::json!({"type": "add_ok"})))?;
+            Ok(())
+        }
+    }
+
+    fn fragile_script() -> Vec<serde_json::Value> {
+        ["boom", "add"]
+            .iter()
+            .zip(1..)
+            .map(|(ty, msg_id)| {
+                serde_json::json!({
+                    "src": "c1",
+                    "dest": "n0",
+                    "body": {"type": ty, "msg_id": msg_id},
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn panicking_handlers_reply_with_crash_and_continue() {
+        let (client, wire) = testing::client(0, 1, &fragile_script());
+
+        node::run_with(client, Fragile).unwrap();
+
+        let replies = wire.messages();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0]["dest"], "c1");
+        assert_eq!(replies[0]["body"]["in_reply_to"], 1);
+        assert_eq!(replies[0]["body"]["code"], ErrorCode::CRASH.0);
+        assert_eq!(replies[1]["body"]["type"], "add_ok");
+        assert_eq!(replies[1]["body"]["in_reply_to"], 2);
+    }
+
+    #[test]
+    fn panicking_handlers_take_the_node_down_when_exiting() {
+        let (mut client, wire) = testing::client(0, 1, &fragile_script());
+        client.set_panic_policy(PanicPolicy::Exit);
+
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| node::run_with(client, Fragile)));
+        assert!(result.is_err());
+
+        let replies = wire.messages();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0]["body"]["code"], ErrorCode::CRASH.0);
+    }
+    fn add_request() -> serde_json::Value {
+        serde_json::json!({
+            "src": "c1",
+            "dest": "n0",
+            "body": {"type": "add", "msg_id": 5, "delta": 1},
+        })
+    }
+
+    #[test]
+    fn forwarded_replies_are_relayed_to_the_requester() {
+        let reply = serde_json::json!({
+            "src": "n1",
+            "dest": "n0",
+            "body": {"type": "add_ok", "msg_id": 9, "in_reply_to": 1},
+        });
+        let (mut client, wire) = testing::client(0, 2, &[add_request(), reply]);
+
+        let message = client.read::<serde_json::Value>().unwrap().unwrap();
+        client
+            .forward(Some(NodeId::node(1)), &message, Duration::from_secs(1))
+            .unwrap();
+
+        let forwarded = wire.messages();
+        assert_eq!(forwarded.len(), 1);
+        assert_eq!(forwarded[0]["dest"], "n1");
+        assert_eq!(forwarded[0]["body"]["type"], "add");
+        assert_eq!(forwarded[0]["body"]["delta"], 1);
+        assert_eq!(forwarded[0]["body"]["msg_id"], 1);
+
+        // the reply is relayed, not handed to the node
+        assert!(client.read::<serde_json::Value>().unwrap().is_none());
+
+        let relayed = wire.messages();
+        assert_eq!(relayed.len(), 1);
+        assert_eq!(relayed[0]["src"], "n0");
+        assert_eq!(relayed[0]["dest"], "c1");
+        assert_eq!(relayed[0]["body"]["type"], "add_ok");
+        assert_eq!(relayed[0]["body"]["in_reply_to"], 5);
+    }
+
+    #[test]
+    fn unanswered_forwards_time_out() {
+        // stdin stays open, so that reading can time out
+        let (mut client, stdin, wire) = testing::piped_client(0, 2);
+        client.spawn_reader();
+        stdin.send(&add_request());
+        let message = client.read::<serde_json::Value>().unwrap().unwrap();
+
+        client
+            .forward(Some(NodeId::node(1)), &message, Duration::from_millis(10))
+            .unwrap();
+        wire.frames();
+
+        let incoming = client
+            .next_incoming(Some(Instant::now() + Duration::from_millis(100)))
+            .unwrap();
+        assert!(matches!(incoming, Incoming::Timeout));
+
+        let replies = wire.messages();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0]["dest"], "c1");
+        assert_eq!(replies[0]["body"]["type"], "error");
+        assert_eq!(replies[0]["body"]["code"], ErrorCode::TIMEOUT.0);
+        assert_eq!(replies[0]["body"]["in_reply_to"], 5);
+    }
+
+    #[test]
+    fn forwards_without_a_leader_are_unavailable() {
+        let (mut client, wire) = testing::client(0, 2, &[add_request()]);
+        let message = client.read::<serde_json::Value>().unwrap().unwrap();
+
+        client
+            .forward(None, &message, Duration::from_secs(1))
+            .unwrap();
+
+        let replies = wire.messages();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0]["dest"], "c1");
+        assert_eq!(
+            replies[0]["body"]["code"],
+            ErrorCode::TEMPORARILY_UNAVAILABLE.0
+        );
+        assert_eq!(replies[0]["body"]["in_reply_to"], 5);
+        // nothing was sent, so nothing waits for a reply
+        assert!(client.requests.next_deadline().is_none());
+    }
+}
@@ -0,0 +1,471 @@
+//! Logical clocks for tracking causality between nodes
+//!
+//! The clocks can be used standalone, or [`MaelstromClient::enable_clock`](crate::MaelstromClient::enable_clock)
+//! can be used to install a [`ClockInterceptor`], which stamps every outgoing body with a
+//! `clock` field and advances the clock from every incoming one.
+
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    interceptor::{Flow, Frame, Interceptor},
+    Error, NodeId,
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LamportClock {
+    time: u64,
+}
+
+impl LamportClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    /// Advances the clock for a local or send event
+    pub fn tick(&mut self) -> u64 {
+        self.time += 1;
+        self.time
+    }
+
+    /// Advances the clock past a timestamp received from another node
+    pub fn observe(&mut self, time: u64) -> u64 {
+        self.time = self.time.max(time) + 1;
+        self.time
+    }
+}
+
+/// A vector clock, nodes that were never observed have an implicit time of 0
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(transparent)]
+pub struct VectorClock {
+    /// Without entries of 0, so that equal clocks have the same entries
+    times: BTreeMap<NodeId, u64>,
+}
+
+impl VectorClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, node: NodeId) -> u64 {
+        self.times.get(&node).copied().unwrap_or(0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, u64)> + '_ {
+        self.times.iter().map(|(&node, &time)| (node, time))
+    }
+
+    /// Advances `node`'s entry for a local or send event
+    pub fn increment(&mut self, node: NodeId) -> u64 {
+        let time = self.times.entry(node).or_insert(0);
+        *time += 1;
+        *time
+    }
+
+    /// Takes the entry-wise maximum of both clocks
+    pub fn merge(&mut self, other: &VectorClock) {
+        for (&node, &time) in &other.times {
+            if time > self.get(node) {
+                self.times.insert(node, time);
+            }
+        }
+    }
+
+    /// Merges a received clock and then advances `node`'s entry, as done on receipt of a message
+    pub fn observe(&mut self, node: NodeId, other: &VectorClock) {
+        self.merge(other);
+        self.increment(node);
+    }
+
+    /// `None` if neither clock happened before the other
+    pub fn compare(&self, other: &VectorClock) -> Option<Ordering> {
+        let mut ordering = Ordering::Equal;
+
+        for node in self.times.keys().chain(other.times.keys()) {
+            let next = match self.get(*node).cmp(&other.get(*node)) {
+                Ordering::Equal => continue,
+                next => next,
+            };
+
+            if ordering == Ordering::Equal {
+                ordering = next;
+            } else if ordering != next {
+                return None;
+            }
+        }
+
+        Some(ordering)
+    }
+
+    pub fn happened_before(&self, other: &VectorClock) -> bool {
+        self.compare(other) == Some(Ordering::Less)
+    }
+
+    pub fn is_concurrent(&self, other: &VectorClock) -> bool {
+        self.compare(other).is_none()
+    }
+}
+
+impl PartialOrd for VectorClock {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.compare(other)
+    }
+}
+
+/// Entries of 0 are dropped, they are the same as missing ones
+impl<'de> Deserialize<'de> for VectorClock {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let mut times = BTreeMap::<NodeId, u64>::deserialize(deserializer)?;
+        times.retain(|_, time| *time > 0);
+
+        Ok(Self { times })
+    }
+}
+
+/// A hybrid logical clock timestamp, milliseconds since the unix epoch plus a
+/// logical counter to order events within the same millisecond
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct HybridTimestamp {
+    pub wall: u64,
+    pub logical: u32,
+}
+
+/// A hybrid logical clock (Kulkarni et al.), which stays close to physical time
+/// while still respecting causality between nodes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HybridClock {
+    last: HybridTimestamp,
+}
+
+impl HybridClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn last(&self) -> HybridTimestamp {
+        self.last
+    }
+
+    /// Advances the clock for a local or send event
+    pub fn tick(&mut self) -> HybridTimestamp {
+        self.tick_at(physical_now())
+    }
+
+    /// Advances the clock past a timestamp received from another node
+    pub fn observe(&mut self, remote: HybridTimestamp) -> HybridTimestamp {
+        self.observe_at(remote, physical_now())
+    }
+
+    /// Like [`HybridClock::tick`], with an explicit physical time in milliseconds
+    pub fn tick_at(&mut self, physical: u64) -> HybridTimestamp {
+        if physical > self.last.wall {
+            self.last = HybridTimestamp {
+                wall: physical,
+                logical: 0,
+            };
+        } else {
+            self.last.logical += 1;
+        }
+
+        self.last
+    }
+
+    /// Like [`HybridClock::observe`], with an explicit physical time in milliseconds
+    pub fn observe_at(&mut self, remote: HybridTimestamp, physical: u64) -> HybridTimestamp {
+        let wall = self.last.wall.max(remote.wall).max(physical);
+
+        let logical = if wall == self.last.wall && wall == remote.wall {
+            self.last.logical.max(remote.logical) + 1
+        } else if wall == self.last.wall {
+            self.last.logical + 1
+        } else if wall == remote.wall {
+            remote.logical + 1
+        } else {
+            0
+        };
+
+        self.last = HybridTimestamp { wall, logical };
+        self.last
+    }
+}
+
+fn physical_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis() as u64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClockKind {
+    Lamport,
+    Vector,
+    Hybrid,
+}
+
+/// The value carried in the `clock` field of a stamped body
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Timestamp {
+    Lamport(u64),
+    Hybrid(HybridTimestamp),
+    Vector(VectorClock),
+}
+
+/// Any of the clocks in this module, as kept by a client with stamping enabled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Clock {
+    Lamport(LamportClock),
+    Vector(VectorClock),
+    Hybrid(HybridClock),
+}
+
+impl Clock {
+    pub fn new(kind: ClockKind) -> Self {
+        match kind {
+            ClockKind::Lamport => Self::Lamport(LamportClock::new()),
+            ClockKind::Vector => Self::Vector(VectorClock::new()),
+            ClockKind::Hybrid => Self::Hybrid(HybridClock::new()),
+        }
+    }
+
+    pub fn kind(&self) -> ClockKind {
+        match self {
+            Self::Lamport(_) => ClockKind::Lamport,
+            Self::Vector(_) => ClockKind::Vector,
+            Self::Hybrid(_) => ClockKind::Hybrid,
+        }
+    }
+
+    /// The current time, without advancing the clock
+    pub fn now(&self) -> Timestamp {
+        match self {
+            Self::Lamport(clock) => Timestamp::Lamport(clock.time()),
+            Self::Vector(clock) => Timestamp::Vector(clock.clone()),
+            Self::Hybrid(clock) => Timestamp::Hybrid(clock.last()),
+        }
+    }
+
+    /// Advances the clock for an event on `node`
+    pub fn tick(&mut self, node: NodeId) -> Timestamp {
+        match self {
+            Self::Lamport(clock) => Timestamp::Lamport(clock.tick()),
+            Self::Vector(clock) => {
+                clock.increment(node);
+                Timestamp::Vector(clock.clone())
+            }
+            Self::Hybrid(clock) => Timestamp::Hybrid(clock.tick()),
+        }
+    }
+
+    /// Advances the clock on `node` past a received timestamp, timestamps from
+    /// a different kind of clock are ignored
+    pub fn observe(&mut self, node: NodeId, timestamp: &Timestamp) {
+        match (self, timestamp) {
+            (Self::Lamport(clock), &Timestamp::Lamport(time)) => {
+                clock.observe(time);
+            }
+            (Self::Vector(clock), Timestamp::Vector(other)) => clock.observe(node, other),
+            (Self::Hybrid(clock), &Timestamp::Hybrid(remote)) => {
+                clock.observe(remote);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Stamps outgoing bodies with the current time of a shared clock, and advances it
+/// with the `clock` field of incoming bodies
+///
+/// Replies to clients are not stamped, they are not part of the causal order of
+/// the nodes. The interceptor has to come before a [`TranscriptRecorder`](crate::transcript::TranscriptRecorder)
+/// in the chain, or recorded frames lack their `clock` and replays diverge, which
+/// is why [`MaelstromClient::enable_clock`](crate::MaelstromClient::enable_clock) installs it first.
+pub struct ClockInterceptor {
+    node_id: NodeId,
+    clock: Arc<Mutex<Clock>>,
+}
+
+impl ClockInterceptor {
+    pub fn new(node_id: NodeId, clock: Arc<Mutex<Clock>>) -> Self {
+        Self { node_id, clock }
+    }
+}
+
+impl Interceptor for ClockInterceptor {
+    /// Frames that are not messages or have an invalid `clock` are passed on
+    /// as they are, deserializing them reports the problem
+    fn on_read(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+        let timestamp = frame.envelope().and_then(|envelope| {
+            envelope
+                .body
+                .get("clock")
+                .map(Timestamp::deserialize)
+                .transpose()
+                .map_err(Error::from)
+        });
+
+        match timestamp {
+            Ok(Some(timestamp)) => self.clock.lock().unwrap().observe(self.node_id, &timestamp),
+            Ok(None) => (),
+            Err(err) => {
+                eprintln!("Not observing the clock of a frame: {err}");
+                eprintln!("{}", bstr::BStr::new(frame.as_bytes()));
+            }
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    fn on_write(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+        let mut envelope = frame.envelope()?;
+        if envelope.dest.is_client() {
+            return Ok(Flow::Continue);
+        }
+
+        let timestamp = self.clock.lock().unwrap().tick(self.node_id);
+        envelope
+            .body
+            .insert("clock".into(), serde_json::to_value(timestamp)?);
+        frame.set_envelope(&envelope)?;
+
+        Ok(Flow::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interceptor() -> (ClockInterceptor, Arc<Mutex<Clock>>) {
+        let clock = Arc::new(Mutex::new(Clock::new(ClockKind::Lamport)));
+        (ClockInterceptor::new(NodeId::node(1), clock.clone()), clock)
+    }
+
+    fn vector(times: &[(u32, u64)]) -> VectorClock {
+        let times = times.iter().map(|&(node, time)| (NodeId::node(node), time));
+        serde_json::from_value(serde_json::to_value(BTreeMap::from_iter(times)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn lamport_clocks_advance_past_what_they_observe() {
+        let mut clock = LamportClock::new();
+
+        assert_eq!(clock.tick(), 1);
+        assert_eq!(clock.observe(5), 6);
+        assert_eq!(clock.observe(2), 7);
+    }
+
+    #[test]
+    fn vector_clocks_compare_entry_wise() {
+        let (a, b) = (vector(&[(1, 1)]), vector(&[(1, 2), (2, 1)]));
+
+        assert_eq!(a.compare(&b), Some(Ordering::Less));
+        assert_eq!(b.compare(&a), Some(Ordering::Greater));
+        assert!(a.happened_before(&b) && !b.happened_before(&a));
+        assert!(a < b);
+
+        let c = vector(&[(2, 3)]);
+        assert_eq!(a.compare(&c), None);
+        assert!(a.is_concurrent(&c) && c.is_concurrent(&a));
+        assert!(!a.is_concurrent(&b));
+        assert!(a.partial_cmp(&c).is_none());
+    }
+
+    #[test]
+    fn vector_clocks_without_zero_entries_are_equal() {
+        let zero = vector(&[(1, 0)]);
+        assert_eq!(zero, VectorClock::new());
+        assert_eq!(zero.partial_cmp(&VectorClock::new()), Some(Ordering::Equal));
+
+        let mut merged = VectorClock::new();
+        merged.merge(&zero);
+        assert_eq!(merged, VectorClock::new());
+        assert_eq!(serde_json::to_string(&merged).unwrap(), "{}");
+    }
+
+    #[test]
+    fn vector_clocks_merge_and_observe() {
+        let mut clock = vector(&[(1, 3), (2, 1)]);
+        clock.merge(&vector(&[(1, 2), (2, 4), (3, 1)]));
+        assert_eq!(clock, vector(&[(1, 3), (2, 4), (3, 1)]));
+
+        clock.observe(NodeId::node(1), &vector(&[(3, 5)]));
+        assert_eq!(clock, vector(&[(1, 4), (2, 4), (3, 5)]));
+        assert_eq!(clock.get(NodeId::node(4)), 0);
+        assert_eq!(clock.increment(NodeId::node(4)), 1);
+    }
+
+    #[test]
+    fn hybrid_clocks_send_and_receive() {
+        let mut clock = HybridClock::new();
+        let at = |wall, logical| HybridTimestamp { wall, logical };
+
+        assert_eq!(clock.tick_at(100), at(100, 0));
+        // physical time did not move, or went back
+        assert_eq!(clock.tick_at(100), at(100, 1));
+        assert_eq!(clock.tick_at(90), at(100, 2));
+
+        // a remote timestamp from the same millisecond
+        assert_eq!(clock.observe_at(at(100, 7), 100), at(100, 8));
+        // a remote wall time that is ahead of this node
+        assert_eq!(clock.observe_at(at(150, 3), 120), at(150, 4));
+        assert_eq!(clock.tick_at(130), at(150, 5));
+        // a remote wall time that is behind
+        assert_eq!(clock.observe_at(at(140, 9), 130), at(150, 6));
+        // physical time that is ahead of both
+        assert_eq!(clock.observe_at(at(150, 9), 200), at(200, 0));
+        assert_eq!(clock.last(), at(200, 0));
+    }
+
+    #[test]
+    fn passes_invalid_frames_through() {
+        let (mut interceptor, clock) = interceptor();
+
+        for frame in [
+            &b"not json"[..],
+            br#"{"src":"n2","dest":"n1","body":{"type":"gossip","clock":"noon"}}"#,
+        ] {
+            let mut read = Frame::new(frame.to_vec());
+            assert_eq!(interceptor.on_read(&mut read).unwrap(), Flow::Continue);
+            assert_eq!(read.as_bytes(), frame);
+        }
+
+        assert_eq!(clock.lock().unwrap().now(), Timestamp::Lamport(0));
+    }
+
+    #[test]
+    fn observes_and_stamps_nodes_only() {
+        let (mut interceptor, clock) = interceptor();
+
+        let mut frame =
+            Frame::new(br#"{"src":"n2","dest":"n1","body":{"type":"gossip","clock":7}}"#.to_vec());
+        assert_eq!(interceptor.on_read(&mut frame).unwrap(), Flow::Continue);
+        assert_eq!(clock.lock().unwrap().now(), Timestamp::Lamport(8));
+
+        let reply = br#"{"src":"n1","dest":"c3","body":{"type":"read_ok","in_reply_to":1}}"#;
+        let mut frame = Frame::new(reply.to_vec());
+        assert_eq!(interceptor.on_write(&mut frame).unwrap(), Flow::Continue);
+        assert_eq!(frame.as_bytes(), reply);
+
+        let mut frame =
+            Frame::new(br#"{"src":"n1","dest":"n2","body":{"type":"gossip"}}"#.to_vec());
+        assert_eq!(interceptor.on_write(&mut frame).unwrap(), Flow::Continue);
+        assert_eq!(frame.envelope().unwrap().body["clock"], 9);
+    }
+}
@@ -0,0 +1,319 @@
+//! The members of the cluster, as sent in the init message
+//!
+//! Every query is deterministic, so all nodes get the same answers without
+//! talking to each other. The ring is the node ids in sorted order, and the
+//! [`HashRing`] hashes with a fixed function instead of a randomly keyed one.
+
+use std::hash::{Hash, Hasher};
+
+use crate::{rng::Rng, NodeId};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cluster {
+    node_id: NodeId,
+    /// Sorted, and including `node_id`
+    nodes: Vec<NodeId>,
+    index: usize,
+}
+
+impl Cluster {
+    /// `node_ids` can be in any order, `node_id` is added if it is missing
+    pub fn new(node_id: NodeId, node_ids: impl IntoIterator<Item = NodeId>) -> Self {
+        let mut nodes: Vec<NodeId> = node_ids.into_iter().collect();
+        nodes.push(node_id);
+        nodes.sort();
+        nodes.dedup();
+
+        let index = nodes.binary_search(&node_id).unwrap();
+
+        Self {
+            node_id,
+            nodes,
+            index,
+        }
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    /// Every node, in ring order
+    pub fn nodes(&self) -> &[NodeId] {
+        &self.nodes
+    }
+
+    /// The number of nodes, including this one
+    pub fn size(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// The position of this node in [`Cluster::nodes`]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn contains(&self, node: NodeId) -> bool {
+        self.nodes.binary_search(&node).is_ok()
+    }
+
+    /// The smallest quorum size such that any two quorums have a node in common
+    pub fn majority(&self) -> usize {
+        self.size() / 2 + 1
+    }
+
+    /// Every other node, in ring order starting from the successor
+    pub fn peers(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.successors(self.size() - 1)
+    }
+
+    /// The next `n` nodes after this one on the ring, without wrapping back around to this node
+    pub fn successors(&self, n: usize) -> impl Iterator<Item = NodeId> + '_ {
+        let (before, after) = self.nodes.split_at(self.index);
+
+        after[1..].iter().chain(before).copied().take(n)
+    }
+
+    /// The next node on the ring, which is this node if it is alone
+    pub fn successor(&self) -> NodeId {
+        self.nodes[(self.index + 1) % self.size()]
+    }
+
+    /// The previous node on the ring, which is this node if it is alone
+    pub fn predecessor(&self) -> NodeId {
+        self.nodes[(self.index + self.size() - 1) % self.size()]
+    }
+
+    /// The other nodes in a random order that only depends on `seed`
+    pub fn shuffled_peers(&self, seed: u64) -> Vec<NodeId> {
+        let mut peers: Vec<NodeId> = self.peers().collect();
+        let mut rng = Rng::new(seed);
+
+        for i in (1..peers.len()).rev() {
+            let j = rng.range(0, i as u64 + 1) as usize;
+            peers.swap(i, j);
+        }
+
+        peers
+    }
+
+    pub fn hash_ring(&self) -> HashRing {
+        HashRing::new(&self.nodes)
+    }
+}
+
+/// Consistent hashing of keys to nodes, so that adding or removing a node only
+/// moves the keys next to its points on the ring
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashRing {
+    /// Sorted by hash, each node has [`HashRing::VIRTUAL_NODES`] points
+    points: Vec<(u64, NodeId)>,
+    nodes: usize,
+}
+
+impl HashRing {
+    pub const VIRTUAL_NODES: u32 = 64;
+
+    pub fn new(nodes: &[NodeId]) -> Self {
+        let mut points: Vec<(u64, NodeId)> = nodes
+            .iter()
+            .flat_map(|&node| {
+                (0..Self::VIRTUAL_NODES)
+                    .map(move |point| (stable_hash(&(WireId(node), point)), node))
+            })
+            .collect();
+        points.sort();
+
+        let mut nodes = nodes.to_vec();
+        nodes.sort();
+        nodes.dedup();
+
+        Self {
+            points,
+            nodes: nodes.len(),
+        }
+    }
+
+    /// The node that owns `key`, `None` if the ring is empty
+    pub fn owner<K: Hash + ?Sized>(&self, key: &K) -> Option<NodeId> {
+        self.owners(key).next()
+    }
+
+    /// The distinct nodes after `key` on the ring, the first `n` are its replicas
+    pub fn owners<K: Hash + ?Sized>(&self, key: &K) -> impl Iterator<Item = NodeId> + '_ {
+        let hash = stable_hash(key);
+        let start = self.points.partition_point(|&(point, _)| point < hash);
+
+        let (before, after) = self.points.split_at(start);
+        let mut seen = Vec::with_capacity(self.nodes);
+
+        after
+            .iter()
+            .chain(before)
+            .map(|&(_, node)| node)
+            .filter(move |node| {
+                let new = !seen.contains(node);
+                if new {
+                    seen.push(*node);
+                }
+                new
+            })
+            .take(self.nodes)
+    }
+}
+
+/// A hash that is the same in every process, unlike the one of `HashMap`
+///
+/// Integers are hashed as little endian, so it is the same on every platform
+pub fn stable_hash<K: Hash + ?Sized>(key: &K) -> u64 {
+    let mut hasher = Fnv1a::default();
+    key.hash(&mut hasher);
+
+    // fnv mixes the last bytes poorly, which clusters the points of a node
+    let mut z = hasher.finish();
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Hashes a node id as it is written in messages, so that the points of the ring
+/// do not depend on how [`NodeId`] is represented
+struct WireId(NodeId);
+
+impl Hash for WireId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.with_str(|id| id.hash(state));
+    }
+}
+
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    /// The same on 32 and 64 bit platforms
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes(ids: impl IntoIterator<Item = u32>) -> Vec<NodeId> {
+        ids.into_iter().map(NodeId::node).collect()
+    }
+
+    #[test]
+    fn neighbours_follow_the_sorted_ids() {
+        let cluster = Cluster::new(NodeId::node(2), nodes([4, 0, 3, 1, 2]));
+
+        assert_eq!(cluster.nodes(), nodes(0..5));
+        assert_eq!(cluster.index(), 2);
+        assert_eq!(cluster.majority(), 3);
+        assert_eq!(cluster.successor(), NodeId::node(3));
+        assert_eq!(cluster.predecessor(), NodeId::node(1));
+        assert_eq!(cluster.successors(2).collect::<Vec<_>>(), nodes([3, 4]));
+        assert_eq!(
+            cluster.successors(9).collect::<Vec<_>>(),
+            nodes([3, 4, 0, 1])
+        );
+        assert_eq!(cluster.peers().collect::<Vec<_>>(), nodes([3, 4, 0, 1]));
+
+        let last = Cluster::new(NodeId::node(4), nodes(0..5));
+        assert_eq!(last.successor(), NodeId::node(0));
+        assert_eq!(last.peers().collect::<Vec<_>>(), nodes(0..4));
+    }
+
+    #[test]
+    fn a_single_node_is_its_own_neighbour() {
+        let cluster = Cluster::new(NodeId::node(7), []);
+
+        assert_eq!(cluster.size(), 1);
+        assert_eq!(cluster.majority(), 1);
+        assert_eq!(cluster.successor(), NodeId::node(7));
+        assert_eq!(cluster.predecessor(), NodeId::node(7));
+        assert_eq!(cluster.peers().count(), 0);
+        assert_eq!(cluster.shuffled_peers(1), []);
+    }
+
+    #[test]
+    fn shuffled_peers_only_depend_on_the_seed() {
+        let cluster = Cluster::new(NodeId::node(0), nodes(0..10));
+
+        let mut peers = cluster.shuffled_peers(3);
+        assert_eq!(peers, cluster.shuffled_peers(3));
+        peers.sort();
+        assert_eq!(peers, nodes(1..10));
+    }
+
+    #[test]
+    fn ring_points_hash_node_ids_as_they_are_written() {
+        assert_eq!(stable_hash(&WireId(NodeId::node(3))), stable_hash("n3"));
+        assert_eq!(stable_hash(&WireId(NodeId::client(12))), stable_hash("c12"));
+        assert_eq!(
+            stable_hash(&WireId(NodeId::lin_kv())),
+            stable_hash("lin-kv")
+        );
+    }
+
+    #[test]
+    fn the_ring_is_stable() {
+        let owners = |ring: &HashRing| -> Vec<NodeId> {
+            (0..10u64).map(|key| ring.owner(&key).unwrap()).collect()
+        };
+
+        // fixed, so a change to the hash that moves keys between versions is noticed
+        assert_eq!(stable_hash(&7u64), 0xae25_3598_b337_821e);
+        let ring = HashRing::new(&nodes(0..5));
+        assert_eq!(owners(&ring), nodes([4, 1, 3, 1, 3, 3, 2, 0, 3, 1]));
+        assert_eq!(ring, HashRing::new(&nodes([3, 1, 4, 0, 2])));
+
+        // a new node only takes keys from the others
+        let grown = HashRing::new(&nodes(0..6));
+        assert_eq!(owners(&grown), nodes([4, 1, 5, 1, 3, 3, 5, 0, 3, 1]));
+    }
+
+    #[test]
+    fn owners_are_distinct() {
+        let ring = HashRing::new(&nodes(0..5));
+
+        let mut owners: Vec<NodeId> = ring.owners("key").collect();
+        assert_eq!(owners[0], ring.owner("key").unwrap());
+        owners.sort();
+        assert_eq!(owners, nodes(0..5));
+
+        assert_eq!(HashRing::new(&[]).owner("key"), None);
+    }
+}
@@ -0,0 +1,246 @@
+//! Typed node parameters from the command line and the environment
+//!
+//! A parameter like `fanout` is read from `--fanout 4` or `--fanout=4` on the
+//! command line, or else from the `VORTEX_FANOUT` environment variable, or else
+//! it has the default the binary declared. Maelstrom cannot pass arguments to
+//! nodes, so it has to use the environment.
+//!
+//! ```ignore
+//! let config = Config::from_env()?;
+//! let fanout: usize = config.get("fanout", 12)?;
+//! let interval = config.duration("gossip-interval", Duration::from_millis(200))?;
+//! config.finish()?;
+//! ```
+//!
+//! Durations are in milliseconds, unless they end with `ms` or `s`.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fmt::Display,
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::Error;
+
+pub const ENV_PREFIX: &str = "VORTEX_";
+
+#[derive(Debug, Default)]
+pub struct Config {
+    args: HashMap<String, String>,
+    /// The parameters that were read, see [`Config::finish`]
+    read: RefCell<HashSet<String>>,
+}
+
+impl Config {
+    /// The parameters passed to the process
+    pub fn from_env() -> Result<Self, Error> {
+        Self::from_args(std::env::args_os().skip(1))
+    }
+
+    pub fn from_args(args: impl IntoIterator<Item = impl Into<OsString>>) -> Result<Self, Error> {
+        let mut args = args.into_iter().map(|arg| {
+            arg.into()
+                .into_string()
+                .map_err(|arg| Error::InvalidParam(format!("{arg:?} is not valid unicode")))
+        });
+        let mut config = Self::default();
+
+        while let Some(arg) = args.next() {
+            let arg = arg?;
+            let Some(param) = arg.strip_prefix("--") else {
+                return Err(Error::InvalidParam(format!(
+                    "expected a --parameter, got {arg}"
+                )));
+            };
+
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.to_owned(), value.to_owned()),
+                None => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| Error::InvalidParam(format!("--{param} needs a value")))?;
+                    (param.to_owned(), value?)
+                }
+            };
+
+            config.args.insert(name, value);
+        }
+
+        Ok(config)
+    }
+
+    /// The raw value of a parameter, from the arguments or the environment
+    pub fn raw(&self, name: &str) -> Option<String> {
+        self.read.borrow_mut().insert(name.to_owned());
+
+        self.args
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(env_var(name)).ok())
+    }
+
+    pub fn get<T>(&self, name: &str, default: T) -> Result<T, Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        Ok(self.optional(name)?.unwrap_or(default))
+    }
+
+    /// A parameter without a default, `None` if it was not given
+    pub fn optional<T>(&self, name: &str) -> Result<Option<T>, Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let Some(value) = self.raw(name) else {
+            return Ok(None);
+        };
+
+        value
+            .parse()
+            .map(Some)
+            .map_err(|err| Error::InvalidParam(format!("{name}={value}: {err}")))
+    }
+
+    pub fn duration(&self, name: &str, default: Duration) -> Result<Duration, Error> {
+        let Some(value) = self.raw(name) else {
+            return Ok(default);
+        };
+
+        let (number, unit) = match value.strip_suffix("ms") {
+            Some(millis) => (millis, 1e-3),
+            None => match value.strip_suffix('s') {
+                Some(secs) => (secs, 1.0),
+                None => (value.as_str(), 1e-3),
+            },
+        };
+
+        number
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .and_then(|number| Duration::try_from_secs_f64(number * unit).ok())
+            .ok_or_else(|| Error::InvalidParam(format!("{name}={value} is not a duration")))
+    }
+
+    /// Fails if an argument was passed for a parameter that was never read,
+    /// which is most likely a typo
+    pub fn finish(&self) -> Result<(), Error> {
+        let read = self.read.borrow();
+        let mut unknown: Vec<&String> = self
+            .args
+            .keys()
+            .filter(|name| !read.contains(*name))
+            .collect();
+        unknown.sort();
+
+        match unknown.first() {
+            Some(name) => Err(Error::InvalidParam(format!("unknown parameter --{name}"))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The environment variable of a parameter, `gossip-interval` is `VORTEX_GOSSIP_INTERVAL`
+pub fn env_var(name: &str) -> String {
+    let name = name.to_ascii_uppercase().replace('-', "_");
+    format!("{ENV_PREFIX}{name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(args: &[&str]) -> Config {
+        Config::from_args(args).unwrap()
+    }
+
+    fn invalid<T: std::fmt::Debug>(result: Result<T, Error>) -> String {
+        match result {
+            Err(Error::InvalidParam(message)) => message,
+            result => panic!("expected an invalid parameter, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn parameters_are_read_with_and_without_equals() {
+        let config = config(&["--fanout=4", "--gossip-interval", "150ms", "--name=a=b"]);
+
+        assert_eq!(config.get("fanout", 12).unwrap(), 4);
+        assert_eq!(
+            config.duration("gossip-interval", Duration::ZERO).unwrap(),
+            Duration::from_millis(150)
+        );
+        assert_eq!(config.raw("name").as_deref(), Some("a=b"));
+        config.finish().unwrap();
+    }
+
+    #[test]
+    fn arguments_must_be_parameters_with_values() {
+        assert!(invalid(Config::from_args(["fanout"])).contains("expected a --parameter"));
+        assert!(invalid(Config::from_args(["--fanout"])).contains("needs a value"));
+    }
+
+    #[test]
+    fn the_environment_is_the_fallback() {
+        assert_eq!(env_var("config-test-rate"), "VORTEX_CONFIG_TEST_RATE");
+        std::env::set_var(env_var("config-test-rate"), "7");
+
+        assert_eq!(config(&[]).get("config-test-rate", 1).unwrap(), 7);
+        // arguments win over the environment
+        let config = config(&["--config-test-rate", "9"]);
+        assert_eq!(config.get("config-test-rate", 1).unwrap(), 9);
+    }
+
+    #[test]
+    fn missing_parameters_have_their_default() {
+        let config = config(&[]);
+
+        assert_eq!(config.get("config-test-missing", 12).unwrap(), 12);
+        assert_eq!(config.optional::<u32>("config-test-missing").unwrap(), None);
+        assert_eq!(
+            config
+                .duration("config-test-missing", Duration::from_secs(2))
+                .unwrap(),
+            Duration::from_secs(2)
+        );
+    }
+
+    #[test]
+    fn durations_have_units() {
+        let config = config(&["--a", "250", "--b", "250ms", "--c", "1.5s", "--d", " 3 s"]);
+
+        for (name, millis) in [("a", 250), ("b", 250), ("c", 1500), ("d", 3000)] {
+            assert_eq!(
+                config.duration(name, Duration::ZERO).unwrap(),
+                Duration::from_millis(millis),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_values_are_invalid_params() {
+        let config = config(&["--a", "soon", "--b", "-5ms", "--c", "NaNs", "--d", "twelve"]);
+
+        for name in ["a", "b", "c"] {
+            let message = invalid(config.duration(name, Duration::ZERO));
+            assert!(message.ends_with("is not a duration"), "{message}");
+        }
+        assert!(invalid(config.get("d", 12)).starts_with("d=twelve"));
+        assert!(invalid(config.optional::<usize>("d")).starts_with("d=twelve"));
+    }
+
+    #[test]
+    fn unknown_parameters_are_rejected() {
+        let config = config(&["--fanout", "4", "--fanuot", "5", "--delay", "1"]);
+        config.get("fanout", 12).unwrap();
+
+        // the first unknown one by name
+        assert_eq!(invalid(config.finish()), "unknown parameter --delay");
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// A maelstrom error code, as carried by the `code` field of an `error` body
+///
+/// Codes below 1000 are reserved by maelstrom, the well known ones are provided
+/// as associated constants
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ErrorCode(pub u32);
+
+impl ErrorCode {
+    pub const TIMEOUT: Self = Self(0);
+    pub const NODE_NOT_FOUND: Self = Self(1);
+    pub const NOT_SUPPORTED: Self = Self(10);
+    pub const TEMPORARILY_UNAVAILABLE: Self = Self(11);
+    pub const MALFORMED_REQUEST: Self = Self(12);
+    pub const CRASH: Self = Self(13);
+    pub const ABORT: Self = Self(14);
+    pub const KEY_DOES_NOT_EXIST: Self = Self(20);
+    pub const KEY_ALREADY_EXISTS: Self = Self(21);
+    pub const PRECONDITION_FAILED: Self = Self(22);
+    pub const TXN_CONFLICT: Self = Self(30);
+
+    /// Definite errors guarantee that the request did not take effect,
+    /// indefinite ones (timeouts and crashes) may or may not have
+    pub fn is_definite(self) -> bool {
+        !matches!(self, Self::TIMEOUT | Self::CRASH)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Self::TIMEOUT => "timeout",
+            Self::NODE_NOT_FOUND => "node-not-found",
+            Self::NOT_SUPPORTED => "not-supported",
+            Self::TEMPORARILY_UNAVAILABLE => "temporarily-unavailable",
+            Self::MALFORMED_REQUEST => "malformed-request",
+            Self::CRASH => "crash",
+            Self::ABORT => "abort",
+            Self::KEY_DOES_NOT_EXIST => "key-does-not-exist",
+            Self::KEY_ALREADY_EXISTS => "key-already-exists",
+            Self::PRECONDITION_FAILED => "precondition-failed",
+            Self::TXN_CONFLICT => "txn-conflict",
+            Self(code) => return write!(f, "error {code}"),
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// The body of a maelstrom `error` message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "error")]
+pub struct ErrorPayload {
+    pub code: ErrorCode,
+    pub text: String,
+}
+
+impl ErrorPayload {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Self {
+            code,
+            text: text.into(),
+        }
+    }
+}
@@ -0,0 +1,365 @@
+//! Local fault injection for messages sent between nodes
+//!
+//! Faults only apply to frames addressed to other nodes, clients and maelstrom
+//! services are left alone, the same as with maelstrom's partition nemesis.
+//!
+//! Setting `VORTEX_FAULTS` makes every [`MaelstromClient`](crate::MaelstromClient) install a
+//! [`FaultInjector`] right after the init handshake, for example
+//!
+//! ```text
+//! VORTEX_FAULTS="seed=7,drop=0.1,duplicate=0.05,delay=0.2,delay_ms=10..100,blackhole=n1+n2@2000..5000"
+//! ```
+//!
+//! Black hole windows are in milliseconds since the injector was installed.
+
+use std::{
+    ops::Range,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    interceptor::{Flow, Frame, Interceptor},
+    rng::Rng,
+    Error, NodeId,
+};
+
+pub const ENV: &str = "VORTEX_FAULTS";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FaultConfig {
+    pub seed: u64,
+    /// The probability that a message is dropped
+    pub drop: f64,
+    /// The probability that a message is sent twice
+    pub duplicate: f64,
+    /// The probability that a message is held back for some time in `delay_range`
+    pub delay: f64,
+    pub delay_range: Range<Duration>,
+    pub blackholes: Vec<Blackhole>,
+}
+
+/// Drops every message sent to `nodes` during `window`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blackhole {
+    pub nodes: Vec<NodeId>,
+    pub window: Range<Duration>,
+}
+
+impl FaultConfig {
+    /// Reads the configuration from `VORTEX_FAULTS`, if it is set
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        match std::env::var(ENV) {
+            Ok(spec) => spec.parse().map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+impl FromStr for FaultConfig {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = |message: String| Error::InvalidFaultConfig(message);
+        let probability = |key: &str, value: &str| match value.parse::<f64>() {
+            Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+            _ => Err(invalid(format!(
+                "`{key}` must be between 0 and 1, got `{value}`"
+            ))),
+        };
+        let millis = |value: &str| -> Result<Range<Duration>, Error> {
+            let (start, end) = value.split_once("..").ok_or_else(|| {
+                invalid(format!("expected a range like `10..100`, got `{value}`"))
+            })?;
+            let parse = |value: &str| {
+                value
+                    .parse()
+                    .map(Duration::from_millis)
+                    .map_err(|_| invalid(format!("expected milliseconds, got `{value}`")))
+            };
+            Ok(parse(start)?..parse(end)?)
+        };
+
+        let mut config = FaultConfig::default();
+
+        for option in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (key, value) = option
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("expected `key=value`, got `{option}`")))?;
+
+            match key {
+                "seed" => {
+                    config.seed = value
+                        .parse()
+                        .map_err(|_| invalid(format!("`seed` must be an integer, got `{value}`")))?
+                }
+                "drop" => config.drop = probability(key, value)?,
+                "duplicate" => config.duplicate = probability(key, value)?,
+                "delay" => config.delay = probability(key, value)?,
+                "delay_ms" => config.delay_range = millis(value)?,
+                "blackhole" => {
+                    let (nodes, window) = value.split_once('@').ok_or_else(|| {
+                        invalid(format!("expected `n1+n2@start..end`, got `{value}`"))
+                    })?;
+                    let nodes = nodes
+                        .split('+')
+                        .map(|node| {
+                            node.parse()
+                                .map_err(|_| invalid(format!("invalid node id `{node}`")))
+                        })
+                        .collect::<Result<_, _>>()?;
+
+                    config.blackholes.push(Blackhole {
+                        nodes,
+                        window: millis(window)?,
+                    });
+                }
+                _ => return Err(invalid(format!("unknown option `{key}`"))),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+pub struct FaultInjector {
+    config: FaultConfig,
+    rng: Rng,
+    start: Instant,
+    held: Vec<(Instant, Frame)>,
+}
+
+impl FaultInjector {
+    pub fn new(config: FaultConfig) -> Self {
+        Self {
+            rng: Rng::new(config.seed),
+            config,
+            start: Instant::now(),
+            held: Vec::new(),
+        }
+    }
+
+    fn delay(&mut self) -> Duration {
+        let Range { start, end } = self.config.delay_range;
+        if end <= start {
+            return start;
+        }
+
+        let micros = self
+            .rng
+            .range(start.as_micros() as u64, end.as_micros() as u64);
+        Duration::from_micros(micros)
+    }
+}
+
+impl Interceptor for FaultInjector {
+    /// Frames without a readable `dest` are passed on as they are, injecting
+    /// faults never fails a write
+    fn on_write(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+        #[derive(Deserialize)]
+        struct Destination {
+            dest: NodeId,
+        }
+
+        let Ok(Destination { dest }) = serde_json::from_slice(frame.as_bytes()) else {
+            return Ok(Flow::Continue);
+        };
+        if !dest.is_node() {
+            return Ok(Flow::Continue);
+        }
+
+        let now = Instant::now();
+        let elapsed = now - self.start;
+        let blackholed = self.config.blackholes.iter().any(|blackhole| {
+            blackhole.window.contains(&elapsed) && blackhole.nodes.contains(&dest)
+        });
+
+        if blackholed || self.rng.chance(self.config.drop) {
+            return Ok(Flow::Drop);
+        }
+
+        if self.rng.chance(self.config.duplicate) {
+            self.held.push((now, frame.clone()));
+        }
+
+        if self.rng.chance(self.config.delay) {
+            let delay = self.delay();
+            self.held.push((now + delay, frame.clone()));
+            return Ok(Flow::Drop);
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    fn poll_write(&mut self, released: &mut Vec<Frame>) -> Result<(), Error> {
+        if self.held.is_empty() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        self.held.sort_by_key(|&(due, _)| due);
+
+        let due = self.held.partition_point(|&(due, _)| due <= now);
+        released.extend(self.held.drain(..due).map(|(_, frame)| frame));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{testing, Response};
+
+    fn millis(range: Range<u64>) -> Range<Duration> {
+        Duration::from_millis(range.start)..Duration::from_millis(range.end)
+    }
+
+    fn frame(dest: &str) -> Frame {
+        let frame = json!({"src": "n1", "dest": dest, "body": {"type": "gossip"}});
+        Frame::new(frame.to_string().into_bytes())
+    }
+
+    #[test]
+    fn parses_every_option() {
+        let config: FaultConfig =
+            "seed=7, drop=0.1,duplicate=0.05,delay=1,delay_ms=5..50,blackhole=n1+n2@2000..5000,blackhole=n3@0..10,"
+                .parse()
+                .unwrap();
+
+        assert_eq!(
+            config,
+            FaultConfig {
+                seed: 7,
+                drop: 0.1,
+                duplicate: 0.05,
+                delay: 1.0,
+                delay_range: millis(5..50),
+                blackholes: vec![
+                    Blackhole {
+                        nodes: vec![NodeId::node(1), NodeId::node(2)],
+                        window: millis(2000..5000),
+                    },
+                    Blackhole {
+                        nodes: vec![NodeId::node(3)],
+                        window: millis(0..10),
+                    },
+                ],
+            }
+        );
+        assert_eq!("".parse::<FaultConfig>().unwrap(), FaultConfig::default());
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        for spec in [
+            "drop=1.5",
+            "drop=-0.1",
+            "duplicate=NaN",
+            "delay=often",
+            "seed=-1",
+            "delay_ms=5",
+            "delay_ms=5-50",
+            "delay_ms=a..50",
+            "delay_ms=5..-1",
+            "blackhole=n1",
+            "blackhole=n1+x2@0..10",
+            "blackhole=n1@10",
+            "drop",
+            "loss=0.1",
+        ] {
+            assert!(
+                matches!(
+                    spec.parse::<FaultConfig>(),
+                    Err(Error::InvalidFaultConfig(_))
+                ),
+                "{spec}"
+            );
+        }
+    }
+
+    #[test]
+    fn the_same_seed_makes_the_same_decisions() {
+        let decisions = |seed| {
+            let config = FaultConfig {
+                seed,
+                drop: 0.3,
+                duplicate: 0.3,
+                ..FaultConfig::default()
+            };
+            let mut injector = FaultInjector::new(config);
+
+            (0..200)
+                .map(|_| {
+                    let flow = injector.on_write(&mut frame("n2")).unwrap();
+                    let mut duplicates = Vec::new();
+                    injector.poll_write(&mut duplicates).unwrap();
+                    (flow, duplicates.len())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let first = decisions(7);
+        assert_eq!(first, decisions(7));
+        assert_ne!(first, decisions(8));
+        assert!(first.contains(&(Flow::Drop, 0)));
+        assert!(first.contains(&(Flow::Continue, 1)));
+        assert!(first.contains(&(Flow::Continue, 0)));
+    }
+
+    #[test]
+    fn only_messages_to_nodes_are_faulty() {
+        let config = FaultConfig {
+            drop: 1.0,
+            ..FaultConfig::default()
+        };
+        let mut injector = FaultInjector::new(config);
+
+        assert_eq!(injector.on_write(&mut frame("n2")).unwrap(), Flow::Drop);
+        for mut frame in [
+            frame("c1"),
+            frame("lin-kv"),
+            Frame::new(b"not json".to_vec()),
+        ] {
+            let before = frame.clone();
+            assert_eq!(injector.on_write(&mut frame).unwrap(), Flow::Continue);
+            assert_eq!(frame, before);
+        }
+    }
+
+    #[test]
+    fn blackholes_drop_messages_to_their_nodes() {
+        let config: FaultConfig = "blackhole=n2@0..60000".parse().unwrap();
+        let mut injector = FaultInjector::new(config);
+
+        assert_eq!(injector.on_write(&mut frame("n2")).unwrap(), Flow::Drop);
+        assert_eq!(injector.on_write(&mut frame("n3")).unwrap(), Flow::Continue);
+    }
+
+    #[test]
+    fn delayed_frames_are_written_on_a_tick() {
+        let (mut client, wire) = testing::client(1, 3, &[]);
+        client.add_interceptor(FaultInjector::new(
+            "delay=1,delay_ms=20..30".parse().unwrap(),
+        ));
+
+        let gossip = Response {
+            dest: NodeId::node(2),
+            in_reply_to: None,
+            payload: json!({"type": "gossip"}),
+        };
+        client.write_no_response(&gossip).unwrap();
+        client.notify_tick().unwrap();
+        assert_eq!(wire.frames().len(), 0);
+
+        std::thread::sleep(Duration::from_millis(40));
+        client.notify_tick().unwrap();
+        let messages = wire.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["dest"], "n2");
+    }
+}
@@ -0,0 +1,334 @@
+//! When the lines written to stdout are flushed
+//!
+//! Every flush is a `write` syscall, so flushing after every message is
+//! expensive when a node sends a burst of them, like gossip to all of its
+//! neighbors. Lines are always written whole and in order, the policy only
+//! decides when they leave the buffer.
+//!
+//! The policy can be parsed from a spec, which is what `--flush` and
+//! `VORTEX_FLUSH` of the `vortex` binary take
+//!
+//! ```text
+//! immediate
+//! end-of-handler
+//! batched:bytes=16384,delay_ms=5
+//! ```
+
+use std::{
+    io::{BufWriter, Write},
+    str::FromStr,
+    time::Duration,
+};
+
+/// See [`MaelstromClient::set_flush_policy`](crate::MaelstromClient::set_flush_policy)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    /// Flush after every message
+    #[default]
+    Immediate,
+    /// Flush when the client waits for input, which is after each handler and
+    /// before waiting for the reply of a call
+    ///
+    /// Detached clients never wait for input, so background tasks have to call
+    /// [`MaelstromClient::flush`](crate::MaelstromClient::flush) after each round of writes
+    EndOfHandler,
+    /// Flush once `max_bytes` are buffered, and otherwise every `max_delay`
+    ///
+    /// Calls wait up to `max_delay` longer for their replies, because the
+    /// request can sit in the buffer for that long
+    Batched {
+        max_bytes: usize,
+        max_delay: Duration,
+    },
+}
+
+impl FlushPolicy {
+    pub const DEFAULT_BATCH_BYTES: usize = 16 * 1024;
+    pub const DEFAULT_BATCH_DELAY: Duration = Duration::from_millis(5);
+}
+
+impl FromStr for FlushPolicy {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (kind, options) = spec.split_once(':').unwrap_or((spec, ""));
+
+        match kind.trim() {
+            "immediate" | "end-of-handler" if !options.trim().is_empty() => {
+                Err(format!("{kind} takes no options"))
+            }
+            "immediate" => Ok(Self::Immediate),
+            "end-of-handler" => Ok(Self::EndOfHandler),
+            "batched" => {
+                let mut max_bytes = Self::DEFAULT_BATCH_BYTES;
+                let mut max_delay = Self::DEFAULT_BATCH_DELAY;
+
+                for option in options.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                    let (key, value) = option
+                        .split_once('=')
+                        .ok_or_else(|| format!("expected key=value, got {option}"))?;
+
+                    let number = value
+                        .trim()
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|&number| number > 0)
+                        .ok_or_else(|| format!("{key} must be a positive integer, got {value}"))?;
+
+                    match key.trim() {
+                        "bytes" => max_bytes = number as usize,
+                        "delay_ms" => max_delay = Duration::from_millis(number),
+                        _ => return Err(format!("unknown option {key}")),
+                    }
+                }
+
+                Ok(Self::Batched {
+                    max_bytes,
+                    max_delay,
+                })
+            }
+            _ => Err(format!(
+                "expected immediate, end-of-handler or batched, got {kind}"
+            )),
+        }
+    }
+}
+
+/// Stdout, shared by a client and all clients detached from it
+pub(crate) struct Output {
+    writer: BufWriter<Box<dyn Write + Send>>,
+    policy: FlushPolicy,
+    /// Whether there are lines that were not flushed yet
+    pending: bool,
+    /// Whether a thread flushes batched lines, see [`crate::MaelstromClient::set_flush_policy`]
+    pub(crate) flusher: bool,
+}
+
+impl Output {
+    pub(crate) fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer: BufWriter::new(writer),
+            policy: FlushPolicy::Immediate,
+            pending: false,
+            flusher: false,
+        }
+    }
+
+    pub(crate) fn policy(&self) -> FlushPolicy {
+        self.policy
+    }
+
+    /// Flushes what is buffered, and makes room for a whole batch if needed
+    pub(crate) fn set_policy(&mut self, policy: FlushPolicy) -> std::io::Result<()> {
+        self.flush()?;
+        self.policy = policy;
+
+        if let FlushPolicy::Batched { max_bytes, .. } = policy {
+            // twice the batch, so that a line that goes over it still fits and
+            // the buffer is only ever written out whole
+            let capacity = max_bytes.saturating_mul(2);
+            if self.writer.capacity() < capacity {
+                let writer =
+                    std::mem::replace(&mut self.writer, BufWriter::new(Box::new(std::io::sink())));
+                let writer = writer.into_inner().map_err(|err| err.into_error())?;
+                self.writer = BufWriter::with_capacity(capacity, writer);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn write_line(&mut self, line: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(line)?;
+        self.writer.write_all(b"\n")?;
+        self.pending = true;
+
+        Ok(())
+    }
+
+    /// Called after the lines of a write, flushes if the policy says so
+    pub(crate) fn written(&mut self) -> std::io::Result<()> {
+        match self.policy {
+            FlushPolicy::Immediate => self.flush(),
+            FlushPolicy::EndOfHandler => Ok(()),
+            FlushPolicy::Batched { max_bytes, .. } if self.writer.buffer().len() >= max_bytes => {
+                self.flush()
+            }
+            FlushPolicy::Batched { .. } => Ok(()),
+        }
+    }
+
+    /// Called before the client waits for input
+    pub(crate) fn waiting(&mut self) -> std::io::Result<()> {
+        match self.policy {
+            FlushPolicy::EndOfHandler => self.flush(),
+            FlushPolicy::Immediate | FlushPolicy::Batched { .. } => Ok(()),
+        }
+    }
+
+    pub(crate) fn flush(&mut self) -> std::io::Result<()> {
+        if std::mem::take(&mut self.pending) {
+            self.writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Stdout that remembers what every `write` got and counts the flushes
+    #[derive(Clone, Default)]
+    struct Stdout(Arc<Mutex<(Vec<Vec<u8>>, usize)>>);
+
+    impl Write for Stdout {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().0.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.lock().unwrap().1 += 1;
+            Ok(())
+        }
+    }
+
+    impl Stdout {
+        fn writes(&self) -> Vec<Vec<u8>> {
+            self.0.lock().unwrap().0.clone()
+        }
+
+        fn flushes(&self) -> usize {
+            self.0.lock().unwrap().1
+        }
+    }
+
+    fn output(policy: FlushPolicy) -> (Output, Stdout) {
+        let stdout = Stdout::default();
+        let mut output = Output::new(Box::new(stdout.clone()));
+        output.set_policy(policy).unwrap();
+        (output, stdout)
+    }
+
+    /// Writes `lines` one message at a time, and waits for input after the handler
+    fn handle(output: &mut Output, lines: &[&str]) {
+        for line in lines {
+            output.write_line(line.as_bytes()).unwrap();
+            output.written().unwrap();
+        }
+        output.waiting().unwrap();
+    }
+
+    #[test]
+    fn immediate_flushes_every_message() {
+        let (mut output, stdout) = output(FlushPolicy::Immediate);
+
+        handle(&mut output, &["a", "b", "c"]);
+        assert_eq!(stdout.flushes(), 3);
+        assert_eq!(stdout.writes(), [b"a\n", b"b\n", b"c\n"]);
+
+        // nothing is pending
+        output.flush().unwrap();
+        assert_eq!(stdout.flushes(), 3);
+    }
+
+    #[test]
+    fn end_of_handler_flushes_when_waiting() {
+        let (mut output, stdout) = output(FlushPolicy::EndOfHandler);
+
+        output.write_line(b"a").unwrap();
+        output.written().unwrap();
+        output.write_line(b"b").unwrap();
+        output.written().unwrap();
+        assert_eq!(stdout.flushes(), 0);
+        assert!(stdout.writes().is_empty());
+
+        output.waiting().unwrap();
+        assert_eq!(stdout.flushes(), 1);
+        assert_eq!(stdout.writes(), [b"a\nb\n"]);
+
+        output.waiting().unwrap();
+        assert_eq!(stdout.flushes(), 1);
+    }
+
+    #[test]
+    fn batched_flushes_full_batches() {
+        let (mut output, stdout) = output(FlushPolicy::Batched {
+            max_bytes: 8,
+            max_delay: Duration::from_secs(60),
+        });
+
+        // a batch is full after the third line of three bytes
+        handle(&mut output, &["aa", "bb"]);
+        assert_eq!(stdout.flushes(), 0);
+        handle(&mut output, &["cc", "dd"]);
+        assert_eq!(stdout.flushes(), 1);
+
+        // the rest waits for the flusher
+        output.flush().unwrap();
+        assert_eq!(stdout.flushes(), 2);
+    }
+
+    #[test]
+    fn lines_stay_whole_and_in_order_across_batches() {
+        let (mut output, stdout) = output(FlushPolicy::Batched {
+            max_bytes: 10,
+            max_delay: Duration::from_secs(60),
+        });
+
+        let lines: Vec<String> = (0..50).map(|i| format!("line {i}")).collect();
+        for line in &lines {
+            output.write_line(line.as_bytes()).unwrap();
+            output.written().unwrap();
+        }
+        output.flush().unwrap();
+
+        let writes = stdout.writes();
+        assert!(writes.len() > 1);
+        assert!(writes.iter().all(|write| write.ends_with(b"\n")));
+
+        let written = String::from_utf8(writes.concat()).unwrap();
+        assert_eq!(written.lines().collect::<Vec<_>>(), lines);
+    }
+
+    #[test]
+    fn policies_are_parsed_from_specs() {
+        assert_eq!("immediate".parse(), Ok(FlushPolicy::Immediate));
+        assert_eq!(" end-of-handler ".parse(), Ok(FlushPolicy::EndOfHandler));
+        assert_eq!(
+            "batched".parse(),
+            Ok(FlushPolicy::Batched {
+                max_bytes: FlushPolicy::DEFAULT_BATCH_BYTES,
+                max_delay: FlushPolicy::DEFAULT_BATCH_DELAY,
+            })
+        );
+        assert_eq!(
+            "batched:bytes=1024, delay_ms=2".parse(),
+            Ok(FlushPolicy::Batched {
+                max_bytes: 1024,
+                max_delay: Duration::from_millis(2),
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_specs_are_rejected() {
+        for spec in [
+            "",
+            "eventually",
+            "immediate:bytes=1",
+            "end-of-handler:delay_ms=1",
+            "batched:bytes",
+            "batched:bytes=0",
+            "batched:bytes=-1",
+            "batched:delay_ms=soon",
+            "batched:lines=4",
+        ] {
+            assert!(spec.parse::<FlushPolicy>().is_err(), "{spec}");
+        }
+    }
+}
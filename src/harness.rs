@@ -0,0 +1,586 @@
+//! Runs node binaries against a workload, without maelstrom
+//!
+//! The harness spawns the nodes, does the init handshake, routes the messages
+//! between them, hosts `seq-kv` and `lin-kv`, and plays the clients of a
+//! [`Workload`]. Every client sends one request at a time to a random node, and
+//! together they send about `rate` requests per second. Once the time limit
+//! passed, the nodes get some time to converge before every node gets the final
+//! request of the workload, if it has one.
+//!
+//! The nodes are called `n0`, `n1`, ..., the clients `c1`, `c2`, ..., and `c0`
+//! sends the init and setup messages.
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fmt,
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    history::{self, Body, Event, EventKind},
+    metrics::{Histogram, LatencySummary},
+    rng::Rng,
+    Error, NodeId,
+};
+
+use services::Services;
+
+mod services;
+pub mod workload;
+
+pub use workload::Workload;
+
+/// How long the nodes get to reply to the init and setup messages
+const SETUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the nodes get to exit once their stdin is closed, before they are killed
+const GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub binary: PathBuf,
+    pub args: Vec<OsString>,
+    pub node_count: usize,
+    pub concurrency: usize,
+    /// Requests per second, over all clients
+    pub rate: f64,
+    pub time_limit: Duration,
+    /// How long a client waits for a reply, after which the request is indeterminate
+    pub timeout: Duration,
+    /// How long the nodes get to converge before the final requests
+    pub recovery: Duration,
+    pub seed: u64,
+    /// Whether the nodes log to stderr, they are silenced by default
+    pub log_stderr: bool,
+}
+
+impl Config {
+    pub fn new(binary: impl Into<PathBuf>) -> Self {
+        Self {
+            binary: binary.into(),
+            args: Vec::new(),
+            node_count: 3,
+            concurrency: 4,
+            rate: 50.0,
+            time_limit: Duration::from_secs(5),
+            timeout: Duration::from_secs(1),
+            recovery: Duration::from_secs(1),
+            seed: 0,
+            log_stderr: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub workload: &'static str,
+    /// The events of the clients, see [`crate::history`]
+    pub history: Vec<Event>,
+    /// The events of the final requests
+    pub finals: Vec<Event>,
+    pub operations: usize,
+    pub ok: usize,
+    pub fail: usize,
+    pub info: usize,
+    /// Messages between nodes and services, the ones from and to clients are not counted
+    pub server_messages: u64,
+    /// Time from sending a request to its successful reply
+    pub latency: LatencySummary,
+    /// Why the run is invalid, if it is
+    pub verdict: Result<(), String>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.verdict.is_ok()
+    }
+
+    pub fn msgs_per_op(&self) -> f64 {
+        self.server_messages as f64 / self.operations.max(1) as f64
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {} operations, {} ok, {} fail, {} info",
+            self.workload, self.operations, self.ok, self.fail, self.info
+        )?;
+        writeln!(
+            f,
+            "msgs-per-op: {:.2} ({} messages between servers)",
+            self.msgs_per_op(),
+            self.server_messages
+        )?;
+
+        let latency = &self.latency;
+        if latency.count > 0 {
+            writeln!(
+                f,
+                "latency: p50 {}us, p90 {}us, p99 {}us, max {}us",
+                latency.p50_us, latency.p90_us, latency.p99_us, latency.max_us
+            )?;
+        }
+
+        match &self.verdict {
+            Ok(()) => writeln!(f, "valid"),
+            Err(reason) => writeln!(f, "invalid: {reason}"),
+        }
+    }
+}
+
+/// Runs the nodes of `config` against `workload`
+pub fn run(config: &Config, workload: &mut dyn Workload) -> Result<Report, Error> {
+    let mut run = Run::spawn(config)?;
+    let result = run.drive(workload);
+    run.close()?;
+    let finals = result?;
+
+    let mut report = Report {
+        workload: workload.name(),
+        history: run.history,
+        finals: Vec::new(),
+        operations: 0,
+        ok: 0,
+        fail: 0,
+        info: 0,
+        server_messages: run.server_messages,
+        latency: Histogram::default().summary(),
+        verdict: Ok(()),
+    };
+    report.finals = report.history.split_off(finals);
+
+    let mut latency = Histogram::default();
+    for operation in history::operations(&report.history) {
+        report.operations += 1;
+        match operation.kind {
+            EventKind::Ok => report.ok += 1,
+            EventKind::Fail => report.fail += 1,
+            EventKind::Info | EventKind::Invoke => report.info += 1,
+        }
+
+        if let (EventKind::Ok, Some(completed)) = (operation.kind, operation.completed) {
+            latency.record(Duration::from_micros(completed - operation.invoked));
+        }
+    }
+    report.latency = latency.summary();
+
+    report.verdict = match run.exited.first() {
+        Some(node) => Err(format!("{} exited early", node)),
+        None => workload.check(&report.history, &report.finals),
+    };
+
+    Ok(report)
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    src: NodeId,
+    dest: NodeId,
+    body: Body,
+}
+
+enum Output {
+    Frame(usize, Vec<u8>),
+    Exited(usize),
+}
+
+struct Process {
+    id: NodeId,
+    child: Child,
+    /// `None` once the node can no longer be written to
+    stdin: Option<ChildStdin>,
+}
+
+struct Client {
+    id: NodeId,
+    process: u32,
+    next_at: Instant,
+}
+
+struct Request {
+    /// `None` for requests that are not recorded in the history
+    process: Option<u32>,
+    node: NodeId,
+    deadline: Instant,
+}
+
+struct Run<'a> {
+    config: &'a Config,
+    start: Instant,
+    nodes: Vec<Process>,
+    outputs: Receiver<Output>,
+    services: Services,
+    rng: Rng,
+    msg_id: u32,
+    /// By the client and `msg_id` of the request
+    pending: HashMap<(NodeId, u32), Request>,
+    /// The replies to requests that are not recorded, `None` if they timed out
+    unrecorded: Vec<(NodeId, Option<Body>)>,
+    history: Vec<Event>,
+    server_messages: u64,
+    exited: Vec<NodeId>,
+    closing: bool,
+}
+
+impl<'a> Run<'a> {
+    fn spawn(config: &'a Config) -> Result<Self, Error> {
+        let (sender, outputs) = mpsc::channel();
+        let mut nodes = Vec::new();
+
+        for index in 0..config.node_count {
+            let stderr = match config.log_stderr {
+                true => Stdio::inherit(),
+                false => Stdio::null(),
+            };
+            let mut child = Command::new(&config.binary)
+                .args(&config.args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(stderr)
+                .spawn()?;
+
+            let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                for line in stdout.split(b'\n').map_while(Result::ok) {
+                    if !line.is_empty() && sender.send(Output::Frame(index, line)).is_err() {
+                        return;
+                    }
+                }
+                let _ = sender.send(Output::Exited(index));
+            });
+
+            nodes.push(Process {
+                id: NodeId::node(index as u32),
+                stdin: child.stdin.take(),
+                child,
+            });
+        }
+
+        Ok(Self {
+            config,
+            start: Instant::now(),
+            nodes,
+            outputs,
+            services: Services::default(),
+            rng: Rng::new(config.seed),
+            msg_id: 0,
+            pending: HashMap::new(),
+            unrecorded: Vec::new(),
+            history: Vec::new(),
+            server_messages: 0,
+            exited: Vec::new(),
+            closing: false,
+        })
+    }
+
+    /// Runs every phase of the workload, returns where the final events start in the history
+    fn drive(&mut self, workload: &mut dyn Workload) -> Result<usize, Error> {
+        let node_ids: Vec<NodeId> = self.nodes.iter().map(|node| node.id).collect();
+
+        let setup = NodeId::client(0);
+        for &node in &node_ids {
+            let init = serde_json::json!({
+                "type": "init",
+                "node_id": node,
+                "node_ids": node_ids,
+            });
+            let serde_json::Value::Object(init) = init else {
+                unreachable!()
+            };
+            self.send(setup, None, node, init, SETUP_TIMEOUT)?;
+        }
+        self.wait_idle()?;
+        self.check_setup("init")?;
+
+        for (node, body) in workload.setup(&node_ids) {
+            self.send(setup, None, node, body, SETUP_TIMEOUT)?;
+        }
+        self.wait_idle()?;
+        self.check_setup("setup")?;
+
+        let concurrency = self.config.concurrency.max(1);
+        let interval = Duration::from_secs_f64(concurrency as f64 / self.config.rate);
+        let now = Instant::now();
+        let mut clients: Vec<Client> = (1..=concurrency as u32)
+            .map(|process| Client {
+                id: NodeId::client(process),
+                process,
+                // spreads the clients out over the first interval
+                next_at: now + interval.mul_f64(self.rng.range(0, 1000) as f64 / 1000.0),
+            })
+            .collect();
+
+        let end = now + self.config.time_limit;
+        while Instant::now() < end {
+            let now = Instant::now();
+            for client in &mut clients {
+                let busy = self.pending.keys().any(|&(id, _)| id == client.id);
+                if busy || client.next_at > now {
+                    continue;
+                }
+
+                let node = node_ids[self.rng.range(0, node_ids.len() as u64) as usize];
+                let request = workload.generate();
+                self.send(
+                    client.id,
+                    Some(client.process),
+                    node,
+                    request,
+                    self.config.timeout,
+                )?;
+                client.next_at = now + interval;
+            }
+
+            // busy clients wait for a reply or a timeout, which `step` already wakes up for
+            let wake = clients
+                .iter()
+                .filter(|client| !self.pending.keys().any(|&(id, _)| id == client.id))
+                .map(|client| client.next_at)
+                .min();
+            self.step(wake.unwrap_or(end).clamp(now, end))?;
+        }
+        self.wait_idle()?;
+
+        let finals = self.history.len();
+        if let Some(request) = workload.final_request() {
+            let recovered = Instant::now() + self.config.recovery;
+            while Instant::now() < recovered {
+                self.step(recovered)?;
+            }
+
+            for (index, &node) in node_ids.iter().enumerate() {
+                let process = (concurrency + 1 + index) as u32;
+                let client = NodeId::client(process);
+                self.send(
+                    client,
+                    Some(process),
+                    node,
+                    request.clone(),
+                    self.config.timeout,
+                )?;
+            }
+            self.wait_idle()?;
+        }
+
+        Ok(finals)
+    }
+
+    fn ts(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    fn send(
+        &mut self,
+        client: NodeId,
+        process: Option<u32>,
+        node: NodeId,
+        mut body: Body,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        self.msg_id += 1;
+        body.insert("msg_id".to_owned(), self.msg_id.into());
+
+        if let Some(process) = process {
+            self.history.push(Event {
+                ts: self.ts(),
+                process,
+                kind: EventKind::Invoke,
+                node,
+                body: body.clone(),
+            });
+        }
+        self.pending.insert(
+            (client, self.msg_id),
+            Request {
+                process,
+                node,
+                deadline: Instant::now() + timeout,
+            },
+        );
+
+        let frame = serde_json::to_vec(&Envelope {
+            src: client,
+            dest: node,
+            body,
+        })?;
+        self.deliver(node, &frame);
+
+        Ok(())
+    }
+
+    /// Writes a frame to a node, frames to nodes that exited are lost
+    fn deliver(&mut self, node: NodeId, frame: &[u8]) {
+        let Some(process) = self.nodes.iter_mut().find(|process| process.id == node) else {
+            return;
+        };
+        let Some(stdin) = &mut process.stdin else {
+            return;
+        };
+
+        let mut line = Vec::with_capacity(frame.len() + 1);
+        line.extend_from_slice(frame);
+        line.push(b'\n');
+        if stdin.write_all(&line).is_err() {
+            process.stdin = None;
+        }
+    }
+
+    /// Routes what the nodes write until `deadline`, or until the next request times out
+    fn step(&mut self, deadline: Instant) -> Result<(), Error> {
+        let wake = self
+            .pending
+            .values()
+            .map(|request| request.deadline)
+            .min()
+            .map_or(deadline, |timeout| timeout.min(deadline));
+
+        match self
+            .outputs
+            .recv_timeout(wake.saturating_duration_since(Instant::now()))
+        {
+            Ok(Output::Frame(index, frame)) => self.route(index, &frame)?,
+            Ok(Output::Exited(index)) => {
+                if !self.closing {
+                    self.exited.push(self.nodes[index].id);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => {
+                std::thread::sleep(wake.saturating_duration_since(Instant::now()));
+            }
+        }
+
+        self.expire();
+
+        Ok(())
+    }
+
+    fn route(&mut self, index: usize, frame: &[u8]) -> Result<(), Error> {
+        let Ok(Envelope { src, dest, body }) = serde_json::from_slice(frame) else {
+            eprintln!(
+                "Invalid frame from {}: {}",
+                self.nodes[index].id,
+                bstr::BStr::new(frame)
+            );
+            return Ok(());
+        };
+
+        if dest.is_node() {
+            self.server_messages += 1;
+            self.deliver(dest, frame);
+        } else if Services::hosts(dest) {
+            self.server_messages += 1;
+            if let Some(reply) = self.services.handle(dest, &body) {
+                self.server_messages += 1;
+                let reply = serde_json::to_vec(&Envelope {
+                    src: dest,
+                    dest: src,
+                    body: reply,
+                })?;
+                self.deliver(src, &reply);
+            }
+        } else if let Some(in_reply_to) = body
+            .get("in_reply_to")
+            .and_then(serde_json::Value::as_u64)
+            .and_then(|msg_id| u32::try_from(msg_id).ok())
+        {
+            // replies to requests that timed out are dropped, like maelstrom does
+            if let Some(request) = self.pending.remove(&(dest, in_reply_to)) {
+                self.complete(request, Some(body));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn complete(&mut self, request: Request, reply: Option<Body>) {
+        let Some(process) = request.process else {
+            self.unrecorded.push((request.node, reply));
+            return;
+        };
+
+        let kind = reply.as_ref().map_or(EventKind::Info, Event::completion);
+        self.history.push(Event {
+            ts: self.ts(),
+            process,
+            kind,
+            node: request.node,
+            body: reply.unwrap_or_default(),
+        });
+    }
+
+    fn expire(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, request)| request.deadline <= now)
+            .map(|(&key, _)| key)
+            .collect();
+
+        for key in expired {
+            if let Some(request) = self.pending.remove(&key) {
+                self.complete(request, None);
+            }
+        }
+    }
+
+    /// Routes messages until every request completed or timed out
+    fn wait_idle(&mut self) -> Result<(), Error> {
+        while let Some(deadline) = self.pending.values().map(|request| request.deadline).max() {
+            self.step(deadline)?;
+        }
+
+        Ok(())
+    }
+
+    /// Fails if a node did not reply successfully to an init or setup message
+    fn check_setup(&mut self, phase: &str) -> Result<(), Error> {
+        for (node, reply) in self.unrecorded.drain(..) {
+            let failure = match reply {
+                Some(reply) if Event::completion(&reply) == EventKind::Ok => continue,
+                Some(reply) => serde_json::Value::Object(reply).to_string(),
+                None => "no reply".to_owned(),
+            };
+
+            return Err(Error::Harness(format!(
+                "{} failed the {phase}: {failure}",
+                node
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Closes the stdin of every node and waits for them to exit
+    fn close(&mut self) -> Result<(), Error> {
+        self.closing = true;
+        for node in &mut self.nodes {
+            node.stdin = None;
+        }
+
+        let deadline = Instant::now() + GRACE_PERIOD;
+        for node in &mut self.nodes {
+            while node.child.try_wait()?.is_none() {
+                if Instant::now() >= deadline {
+                    node.child.kill()?;
+                    node.child.wait()?;
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use crate::{history::Body, ErrorCode, ErrorPayload, NodeId};
+
+/// The key value services maelstrom hosts, see [`crate::kv::rpc`]
+///
+/// Both apply every request atomically in the order they arrive, which is a
+/// valid, if unusually strong, `seq-kv`
+#[derive(Default)]
+pub(crate) struct Services {
+    seq_kv: Kv,
+    lin_kv: Kv,
+    msg_id: u32,
+}
+
+#[derive(Default)]
+struct Kv {
+    /// Keyed by the json of the key, since keys can be any json value
+    values: HashMap<String, Value>,
+}
+
+impl Services {
+    pub fn hosts(dest: NodeId) -> bool {
+        dest.is_seq_kv() || dest.is_lin_kv()
+    }
+
+    /// The reply of the service `dest` to a request, if the request has a `msg_id`
+    pub fn handle(&mut self, dest: NodeId, request: &Body) -> Option<Body> {
+        let in_reply_to = request.get("msg_id")?.clone();
+
+        let kv = if dest.is_seq_kv() {
+            &mut self.seq_kv
+        } else {
+            &mut self.lin_kv
+        };
+
+        let reply = kv.apply(request).unwrap_or_else(|error| {
+            serde_json::to_value(error).expect("error payloads serialize to json")
+        });
+        let Value::Object(mut reply) = reply else {
+            unreachable!("replies are json objects")
+        };
+
+        self.msg_id += 1;
+        reply.insert("msg_id".to_owned(), self.msg_id.into());
+        reply.insert("in_reply_to".to_owned(), in_reply_to);
+
+        Some(reply)
+    }
+}
+
+impl Kv {
+    fn apply(&mut self, request: &Body) -> Result<Value, ErrorPayload> {
+        let field = |name: &str| {
+            request.get(name).ok_or_else(|| {
+                ErrorPayload::new(
+                    ErrorCode::MALFORMED_REQUEST,
+                    format!("missing field `{name}`"),
+                )
+            })
+        };
+
+        let ty = request.get("type").and_then(Value::as_str).unwrap_or("");
+        let key_does_not_exist =
+            || ErrorPayload::new(ErrorCode::KEY_DOES_NOT_EXIST, "key does not exist");
+
+        match ty {
+            "read" => {
+                let value = self
+                    .values
+                    .get(&field("key")?.to_string())
+                    .ok_or_else(key_does_not_exist)?;
+
+                Ok(json!({ "type": "read_ok", "value": value }))
+            }
+            "write" => {
+                let key = field("key")?.to_string();
+                self.values.insert(key, field("value")?.clone());
+
+                Ok(json!({ "type": "write_ok" }))
+            }
+            "cas" => {
+                let key = field("key")?.to_string();
+                let (from, to) = (field("from")?, field("to")?);
+                let create_if_not_exists = request
+                    .get("create_if_not_exists")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false);
+
+                match self.values.get_mut(&key) {
+                    Some(value) if value == from => *value = to.clone(),
+                    Some(value) => {
+                        return Err(ErrorPayload::new(
+                            ErrorCode::PRECONDITION_FAILED,
+                            format!("current value {value} is not {from}"),
+                        ))
+                    }
+                    None if create_if_not_exists => {
+                        self.values.insert(key, to.clone());
+                    }
+                    None => return Err(key_does_not_exist()),
+                }
+
+                Ok(json!({ "type": "cas_ok" }))
+            }
+            _ => Err(ErrorPayload::new(
+                ErrorCode::NOT_SUPPORTED,
+                format!("unknown message type `{ty}`"),
+            )),
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! The workloads the [`harness`](crate::harness) can run, named like maelstrom's
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use crate::{
+    checker::{broadcast, linearizable},
+    history::{self, Body, Event, EventKind},
+    rng::Rng,
+    NodeId,
+};
+
+pub const NAMES: &[&str] = &["echo", "unique-ids", "broadcast", "g-counter", "lin-kv"];
+
+pub trait Workload {
+    fn name(&self) -> &'static str;
+
+    /// Requests sent to each node after the init handshake, before any operation
+    fn setup(&mut self, nodes: &[NodeId]) -> Vec<(NodeId, Body)> {
+        let _ = nodes;
+        Vec::new()
+    }
+
+    /// The next request of a client
+    fn generate(&mut self) -> Body;
+
+    /// A request sent to every node at the end, once the nodes had time to converge
+    fn final_request(&self) -> Option<Body> {
+        None
+    }
+
+    /// Whether the history is valid, with the reason if it is not
+    ///
+    /// The events of the final requests are in `finals` instead of `history`
+    fn check(&self, history: &[Event], finals: &[Event]) -> Result<(), String>;
+}
+
+/// The workload called `name`, see [`NAMES`]
+pub fn by_name(name: &str, seed: u64) -> Option<Box<dyn Workload>> {
+    let rng = Rng::new(seed);
+
+    Some(match name {
+        "echo" => Box::new(Echo { next: 0 }),
+        "unique-ids" => Box::new(UniqueIds),
+        "broadcast" => Box::new(Broadcast { rng, next: 0 }),
+        "g-counter" => Box::new(GCounter { rng }),
+        "lin-kv" => Box::new(LinKv { rng }),
+        _ => return None,
+    })
+}
+
+fn body(value: Value) -> Body {
+    match value {
+        Value::Object(body) => body,
+        _ => unreachable!("bodies are json objects"),
+    }
+}
+
+/// Every operation that succeeded, with its reply
+fn succeeded(history: &[Event]) -> impl Iterator<Item = (history::Operation, Body)> {
+    history::operations(history)
+        .into_iter()
+        .filter(|operation| operation.kind == EventKind::Ok)
+        .filter_map(|mut operation| {
+            let reply = operation.reply.take()?;
+            Some((operation, reply))
+        })
+}
+
+fn none_succeeded() -> Result<(), String> {
+    Err("no operation succeeded".to_owned())
+}
+
+struct Echo {
+    next: u64,
+}
+
+impl Workload for Echo {
+    fn name(&self) -> &'static str {
+        "echo"
+    }
+
+    fn generate(&mut self) -> Body {
+        self.next += 1;
+        body(json!({ "type": "echo", "echo": format!("Please echo {}", self.next) }))
+    }
+
+    fn check(&self, history: &[Event], _: &[Event]) -> Result<(), String> {
+        let mut ok = 0;
+
+        for (operation, reply) in succeeded(history) {
+            if reply.get("type") != Some(&json!("echo_ok"))
+                || reply.get("echo") != operation.request.get("echo")
+            {
+                return Err(format!(
+                    "expected an echo_ok echoing {}, got {}",
+                    Value::Object(operation.request),
+                    Value::Object(reply),
+                ));
+            }
+            ok += 1;
+        }
+
+        match ok {
+            0 => none_succeeded(),
+            _ => Ok(()),
+        }
+    }
+}
+
+struct UniqueIds;
+
+impl Workload for UniqueIds {
+    fn name(&self) -> &'static str {
+        "unique-ids"
+    }
+
+    fn generate(&mut self) -> Body {
+        body(json!({ "type": "generate" }))
+    }
+
+    fn check(&self, history: &[Event], _: &[Event]) -> Result<(), String> {
+        let mut seen = HashMap::new();
+
+        for (operation, reply) in succeeded(history) {
+            let Some(id) = reply.get("id") else {
+                return Err(format!("generate_ok without an id from {}", operation.node));
+            };
+
+            if let Some(first) = seen.insert(id.to_string(), operation.node) {
+                return Err(format!(
+                    "{id} was generated twice, by {} and {}",
+                    first, operation.node
+                ));
+            }
+        }
+
+        match seen.len() {
+            0 => none_succeeded(),
+            _ => Ok(()),
+        }
+    }
+}
+
+struct Broadcast {
+    rng: Rng,
+    next: u64,
+}
+
+/// Connects each node to its neighbours on a square grid, like maelstrom's default topology
+fn grid(nodes: &[NodeId]) -> HashMap<NodeId, Vec<NodeId>> {
+    let width = (nodes.len() as f64).sqrt().ceil().max(1.0) as usize;
+
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(index, &node)| {
+            let neighbours = [
+                index.checked_sub(width),
+                Some(index + width),
+                index.checked_sub(1).filter(|_| index % width != 0),
+                Some(index + 1).filter(|next| next % width != 0),
+            ];
+            let neighbours = neighbours
+                .into_iter()
+                .flatten()
+                .filter_map(|neighbour| nodes.get(neighbour).copied())
+                .collect();
+
+            (node, neighbours)
+        })
+        .collect()
+}
+
+impl Workload for Broadcast {
+    fn name(&self) -> &'static str {
+        "broadcast"
+    }
+
+    fn setup(&mut self, nodes: &[NodeId]) -> Vec<(NodeId, Body)> {
+        let topology = grid(nodes);
+
+        nodes
+            .iter()
+            .map(|&node| {
+                (
+                    node,
+                    body(json!({ "type": "topology", "topology": topology })),
+                )
+            })
+            .collect()
+    }
+
+    fn generate(&mut self) -> Body {
+        if self.rng.chance(0.5) {
+            self.next += 1;
+            body(json!({ "type": "broadcast", "message": self.next }))
+        } else {
+            body(json!({ "type": "read" }))
+        }
+    }
+
+    fn final_request(&self) -> Option<Body> {
+        Some(body(json!({ "type": "read" })))
+    }
+
+    fn check(&self, history: &[Event], finals: &[Event]) -> Result<(), String> {
+        // every node must have done its final read
+        final_reads(finals, "messages", |messages| messages.as_array().map(drop))?;
+
+        let events: Vec<Event> = history.iter().chain(finals).cloned().collect();
+        let report = broadcast::check(&events)?;
+
+        if !report.lost.is_empty() {
+            return Err(format!("lost acknowledged messages {:?}", report.lost));
+        }
+        if !report.unexpected.is_empty() {
+            return Err(format!(
+                "read messages that were never sent {:?}",
+                report.unexpected
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// The value of `field` in the final read of every node
+fn final_reads<T>(
+    finals: &[Event],
+    field: &str,
+    parse: impl Fn(&Value) -> Option<T>,
+) -> Result<Vec<(NodeId, T)>, String> {
+    history::operations(finals)
+        .into_iter()
+        .map(|operation| {
+            let node = operation.node;
+            let value = match operation.reply {
+                Some(reply) if operation.kind == EventKind::Ok => reply.get(field).and_then(&parse),
+                Some(reply) => {
+                    let reply = Value::Object(reply);
+                    return Err(format!("the final read of {node} failed: {reply}"));
+                }
+                None => return Err(format!("the final read of {node} did not complete")),
+            };
+
+            value
+                .map(|value| (operation.node, value))
+                .ok_or_else(|| format!("the final read of {node} has no valid `{field}`"))
+        })
+        .collect()
+}
+
+struct GCounter {
+    rng: Rng,
+}
+
+impl Workload for GCounter {
+    fn name(&self) -> &'static str {
+        "g-counter"
+    }
+
+    fn generate(&mut self) -> Body {
+        if self.rng.chance(0.5) {
+            body(json!({ "type": "add", "delta": self.rng.range(0, 5) }))
+        } else {
+            body(json!({ "type": "read" }))
+        }
+    }
+
+    fn final_request(&self) -> Option<Body> {
+        Some(body(json!({ "type": "read" })))
+    }
+
+    fn check(&self, history: &[Event], finals: &[Event]) -> Result<(), String> {
+        let operations = history::operations(history);
+
+        let (mut lower, mut upper) = (0, 0);
+        for operation in operations
+            .iter()
+            .filter(|operation| operation.ty() == "add")
+        {
+            let delta = operation
+                .request
+                .get("delta")
+                .and_then(Value::as_u64)
+                .unwrap_or(0);
+
+            match operation.kind {
+                EventKind::Ok => {
+                    lower += delta;
+                    upper += delta;
+                }
+                EventKind::Info => upper += delta,
+                EventKind::Invoke | EventKind::Fail => (),
+            }
+        }
+
+        for (node, value) in final_reads(finals, "value", Value::as_u64)? {
+            if !(lower..=upper).contains(&value) {
+                return Err(format!(
+                    "{} read {value}, expected a value in {lower}..={upper}",
+                    node
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct LinKv {
+    rng: Rng,
+}
+
+impl Workload for LinKv {
+    fn name(&self) -> &'static str {
+        "lin-kv"
+    }
+
+    fn generate(&mut self) -> Body {
+        let key = self.rng.range(0, 3);
+
+        match self.rng.range(0, 3) {
+            0 => body(json!({ "type": "read", "key": key })),
+            1 => body(json!({ "type": "write", "key": key, "value": self.rng.range(0, 5) })),
+            _ => body(json!({
+                "type": "cas",
+                "key": key,
+                "from": self.rng.range(0, 5),
+                "to": self.rng.range(0, 5),
+            })),
+        }
+    }
+
+    fn check(&self, history: &[Event], _: &[Event]) -> Result<(), String> {
+        let operations = linearizable::from_history::<u64, u64>(history)?;
+        linearizable::check(&operations).map_err(|counterexample| counterexample.to_string())
+    }
+}
@@ -0,0 +1,155 @@
+//! Histories of client operations, as recorded by the [`harness`](crate::harness)
+//!
+//! Every request a client sends is recorded as an `invoke` event, and its reply
+//! as an `ok` or `fail` event. Requests that timed out or failed with an indefinite
+//! error may or may not have taken effect, they complete with an `info` event.
+//! A history is stored as a jsonl file, one event per line:
+//!
+//! ```text
+//! {"ts":1500,"process":1,"kind":"invoke","node":"n0","body":{"type":"read"}}
+//! {"ts":2100,"process":1,"kind":"ok","node":"n0","body":{"type":"read_ok","messages":[]}}
+//! ```
+//!
+//! where `ts` is in microseconds since the start of the run.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, ErrorCode, NodeId};
+
+pub type Body = serde_json::Map<String, serde_json::Value>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Invoke,
+    Ok,
+    Fail,
+    Info,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub ts: u64,
+    /// The client that sent the request, every client has one request in flight at a time
+    pub process: u32,
+    pub kind: EventKind,
+    /// The node the request was sent to
+    pub node: NodeId,
+    /// The request for `invoke` events, and the reply otherwise, if there was one
+    pub body: Body,
+}
+
+impl Event {
+    /// The `type` of the body
+    pub fn ty(&self) -> &str {
+        self.body
+            .get("type")
+            .and_then(serde_json::Value::as_str)
+            .unwrap_or_default()
+    }
+
+    /// The kind of the event completing a request with `reply`
+    pub fn completion(reply: &Body) -> EventKind {
+        if reply.get("type").and_then(serde_json::Value::as_str) != Some("error") {
+            return EventKind::Ok;
+        }
+
+        let code = reply
+            .get("code")
+            .and_then(serde_json::Value::as_u64)
+            .and_then(|code| u32::try_from(code).ok())
+            .map(ErrorCode);
+
+        match code {
+            Some(code) if code.is_definite() => EventKind::Fail,
+            _ => EventKind::Info,
+        }
+    }
+}
+
+/// A request and how it completed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation {
+    pub process: u32,
+    pub node: NodeId,
+    pub request: Body,
+    pub invoked: u64,
+    /// `Info` if the history ended before the request completed
+    pub kind: EventKind,
+    /// `None` if the request is still pending when the history ends
+    pub completed: Option<u64>,
+    pub reply: Option<Body>,
+}
+
+impl Operation {
+    /// The `type` of the request
+    pub fn ty(&self) -> &str {
+        self.request
+            .get("type")
+            .and_then(serde_json::Value::as_str)
+            .unwrap_or_default()
+    }
+}
+
+/// Pairs every `invoke` event with the next event of the same process, in the
+/// order the requests were invoked
+pub fn operations(events: &[Event]) -> Vec<Operation> {
+    let mut operations = Vec::new();
+    let mut pending = HashMap::new();
+
+    for event in events {
+        if event.kind == EventKind::Invoke {
+            pending.insert(event.process, operations.len());
+            operations.push(Operation {
+                process: event.process,
+                node: event.node,
+                request: event.body.clone(),
+                invoked: event.ts,
+                kind: EventKind::Info,
+                completed: None,
+                reply: None,
+            });
+        } else if let Some(index) = pending.remove(&event.process) {
+            let operation: &mut Operation = &mut operations[index];
+            operation.kind = event.kind;
+            operation.completed = Some(event.ts);
+            operation.reply = (!event.body.is_empty()).then(|| event.body.clone());
+        }
+    }
+
+    operations
+}
+
+/// Reads a history written by [`write`]
+pub fn read(path: impl AsRef<Path>) -> Result<Vec<Event>, Error> {
+    let file = BufReader::new(File::open(path)?);
+    let mut events = Vec::new();
+
+    for line in file.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            events.push(serde_json::from_str(&line)?);
+        }
+    }
+
+    Ok(events)
+}
+
+pub fn write(path: impl AsRef<Path>, events: &[Event]) -> Result<(), Error> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    for event in events {
+        serde_json::to_writer(&mut file, event)?;
+        file.write_all(b"\n")?;
+    }
+    file.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,306 @@
+//! Hooks into every frame a [`MaelstromClient`](crate::MaelstromClient) reads or writes
+//!
+//! Interceptors are ordered from the application towards the wire, outgoing
+//! frames pass through them in the order they were added and incoming frames
+//! in the reverse order. So an interceptor added later always sees what an
+//! earlier one wrote, and sees incoming frames before any earlier one does.
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{Error, Message, NodeId};
+
+/// A single line of the maelstrom protocol, without the trailing newline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    bytes: Vec<u8>,
+}
+
+/// A frame with a parsed but untyped body
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub src: NodeId,
+    pub dest: NodeId,
+    pub body: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[must_use]
+pub enum Flow {
+    Continue,
+    /// Discards the frame, later interceptors never see it
+    Drop,
+}
+
+pub trait Interceptor: Send {
+    /// Called with every frame read from stdin, before it is deserialized
+    fn on_read(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+        let _ = frame;
+        Ok(Flow::Continue)
+    }
+
+    /// Called with every frame before it is written to stdout
+    fn on_write(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+        let _ = frame;
+        Ok(Flow::Continue)
+    }
+
+    /// Called whenever a timer of the node fires, before the node handles it
+    fn on_tick(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called on every write, after `on_write`, to release frames that were held back
+    ///
+    /// Released frames continue through the interceptors after this one
+    fn poll_write(&mut self, released: &mut Vec<Frame>) -> Result<(), Error> {
+        let _ = released;
+        Ok(())
+    }
+}
+
+impl Frame {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Parses the frame as a typed message
+    pub fn message<T: DeserializeOwned>(&self) -> Result<Message<T>, Error> {
+        Ok(serde_json::from_slice(&self.bytes)?)
+    }
+
+    pub fn envelope(&self) -> Result<Envelope, Error> {
+        Ok(serde_json::from_slice(&self.bytes)?)
+    }
+
+    /// Replaces the frame with the serialized envelope
+    pub fn set_envelope(&mut self, envelope: &Envelope) -> Result<(), Error> {
+        self.bytes.clear();
+        serde_json::to_writer(&mut self.bytes, envelope)?;
+        Ok(())
+    }
+}
+
+/// Logs every frame read from stdin to stderr, clients start out with this installed
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StderrLogger {
+    writes: bool,
+}
+
+impl StderrLogger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also log every frame written to stdout
+    pub fn with_writes(self) -> Self {
+        Self { writes: true }
+    }
+}
+
+impl Interceptor for StderrLogger {
+    fn on_read(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+        eprintln!("Read from stdin:");
+        eprintln!("{}", bstr::BStr::new(frame.as_bytes()));
+        Ok(Flow::Continue)
+    }
+
+    fn on_write(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+        if self.writes {
+            eprintln!("Wrote to stdout:");
+            eprintln!("{}", bstr::BStr::new(frame.as_bytes()));
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+/// The interceptors installed on a client, shared with every client detached from it
+#[derive(Default)]
+pub(crate) struct Chain {
+    interceptors: Vec<Box<dyn Interceptor>>,
+}
+
+impl Chain {
+    pub fn push(&mut self, interceptor: Box<dyn Interceptor>) {
+        self.interceptors.push(interceptor);
+    }
+
+    /// Adds an interceptor closest to the application
+    pub fn push_front(&mut self, interceptor: Box<dyn Interceptor>) {
+        self.interceptors.insert(0, interceptor);
+    }
+
+    pub fn clear(&mut self) {
+        self.interceptors.clear();
+    }
+
+    pub fn on_read(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+        for interceptor in self.interceptors.iter_mut().rev() {
+            if interceptor.on_read(frame)? == Flow::Drop {
+                return Ok(Flow::Drop);
+            }
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    pub fn on_tick(&mut self) -> Result<(), Error> {
+        for interceptor in &mut self.interceptors {
+            interceptor.on_tick()?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs `frames` through every interceptor, leaving the frames that should be written
+    pub fn on_write(&mut self, frames: &mut Vec<Frame>) -> Result<(), Error> {
+        for interceptor in &mut self.interceptors {
+            let mut i = 0;
+            while i < frames.len() {
+                match interceptor.on_write(&mut frames[i])? {
+                    Flow::Continue => i += 1,
+                    Flow::Drop => {
+                        frames.remove(i);
+                    }
+                }
+            }
+
+            interceptor.poll_write(frames)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Appends its name to written frames, and logs the order it sees reads in
+    struct Tag {
+        name: &'static str,
+        reads: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Interceptor for Tag {
+        fn on_read(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+            self.reads.lock().unwrap().push(self.name);
+            Ok(match frame.as_bytes() == self.name.as_bytes() {
+                true => Flow::Drop,
+                false => Flow::Continue,
+            })
+        }
+
+        fn on_write(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+            frame.bytes_mut().extend_from_slice(self.name.as_bytes());
+            Ok(match frame.as_bytes().starts_with(b"drop") {
+                true => Flow::Drop,
+                false => Flow::Continue,
+            })
+        }
+    }
+
+    /// Holds back every written frame until the next write
+    #[derive(Default)]
+    struct Hold {
+        held: Vec<Frame>,
+        released: Vec<Frame>,
+    }
+
+    impl Interceptor for Hold {
+        fn on_write(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+            self.held.push(frame.clone());
+            Ok(Flow::Drop)
+        }
+
+        fn poll_write(&mut self, released: &mut Vec<Frame>) -> Result<(), Error> {
+            released.append(&mut self.released);
+            self.released = std::mem::take(&mut self.held);
+            Ok(())
+        }
+    }
+
+    fn chain(names: &[&'static str]) -> (Chain, Arc<Mutex<Vec<&'static str>>>) {
+        let reads = Arc::new(Mutex::new(Vec::new()));
+        let mut chain = Chain::default();
+        for &name in names {
+            chain.push(Box::new(Tag {
+                name,
+                reads: reads.clone(),
+            }));
+        }
+
+        (chain, reads)
+    }
+
+    fn frames(frames: &[&str]) -> Vec<Frame> {
+        frames
+            .iter()
+            .map(|frame| Frame::new(frame.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn writes_go_towards_the_wire_and_reads_come_back() {
+        let (mut chain, reads) = chain(&["a", "b"]);
+        chain.push_front(Box::new(Tag {
+            name: "0",
+            reads: reads.clone(),
+        }));
+
+        let mut written = frames(&["x", "y"]);
+        chain.on_write(&mut written).unwrap();
+        assert_eq!(written, frames(&["x0ab", "y0ab"]));
+
+        let mut read = Frame::new(b"z".to_vec());
+        assert_eq!(chain.on_read(&mut read).unwrap(), Flow::Continue);
+        assert_eq!(*reads.lock().unwrap(), ["b", "a", "0"]);
+    }
+
+    #[test]
+    fn dropped_frames_skip_the_rest_of_the_chain() {
+        let (mut chain, reads) = chain(&["a", "b"]);
+
+        let mut written = frames(&["drop", "keep"]);
+        chain.on_write(&mut written).unwrap();
+        assert_eq!(written, frames(&["keepab"]));
+
+        // `b` is closest to the wire, so `a` never sees its reads
+        let mut read = Frame::new(b"b".to_vec());
+        assert_eq!(chain.on_read(&mut read).unwrap(), Flow::Drop);
+        assert_eq!(*reads.lock().unwrap(), ["b"]);
+    }
+
+    #[test]
+    fn released_frames_continue_after_the_interceptor() {
+        let (mut chain, _) = chain(&["a"]);
+        chain.push(Box::<Hold>::default());
+        let (after, _) = self::chain(&["b"]);
+        chain.interceptors.extend(after.interceptors);
+
+        let mut written = frames(&["x"]);
+        chain.on_write(&mut written).unwrap();
+        assert_eq!(written, []);
+
+        let mut written = frames(&["y"]);
+        chain.on_write(&mut written).unwrap();
+        assert_eq!(written, frames(&["xab"]));
+
+        let mut written = Vec::new();
+        chain.on_write(&mut written).unwrap();
+        assert_eq!(written, frames(&["yab"]));
+    }
+}
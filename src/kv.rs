@@ -72,3 +72,93 @@ impl<'de> serde::Deserialize<'de> for CasError {
         deserializer.deserialize_str(CasErrorVisitor)
     }
 }
+
+/// Requests to the maelstrom key value services, like [`NodeId::seq_kv`](crate::NodeId::seq_kv)
+///
+/// The service can reply with a `key-does-not-exist` (20) error to reads and
+/// with a `precondition-failed` (22) error to compare and sets
+pub mod rpc {
+    use std::marker::PhantomData;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::rpc::{Body, Rpc};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Read<K, V> {
+        pub key: K,
+        #[serde(skip)]
+        value: PhantomData<fn() -> V>,
+    }
+
+    impl<K, V> Read<K, V> {
+        pub fn new(key: K) -> Self {
+            Self {
+                key,
+                value: PhantomData,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct ReadOk<V> {
+        pub value: V,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Write<K, V> {
+        pub key: K,
+        pub value: V,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct WriteOk {}
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Cas<K, V> {
+        pub key: K,
+        pub from: V,
+        pub to: V,
+        #[serde(default)]
+        pub create_if_not_exists: bool,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct CasOk {}
+
+    impl<K, V> Body for Read<K, V> {
+        const TYPE: &'static str = "read";
+    }
+
+    impl<V> Body for ReadOk<V> {
+        const TYPE: &'static str = "read_ok";
+    }
+
+    impl<K, V> Body for Write<K, V> {
+        const TYPE: &'static str = "write";
+    }
+
+    impl Body for WriteOk {
+        const TYPE: &'static str = "write_ok";
+    }
+
+    impl<K, V> Body for Cas<K, V> {
+        const TYPE: &'static str = "cas";
+    }
+
+    impl Body for CasOk {
+        const TYPE: &'static str = "cas_ok";
+    }
+
+    impl<K: Serialize, V: Serialize + serde::de::DeserializeOwned> Rpc for Read<K, V> {
+        type Reply = ReadOk<V>;
+    }
+
+    impl<K: Serialize, V: Serialize> Rpc for Write<K, V> {
+        type Reply = WriteOk;
+    }
+
+    impl<K: Serialize, V: Serialize> Rpc for Cas<K, V> {
+        type Reply = CasOk;
+    }
+}
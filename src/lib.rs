@@ -3,12 +3,44 @@ use std::fmt::Debug;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+#[cfg(feature = "async")]
+pub mod async_client;
+pub mod checker;
 mod client;
+pub mod clock;
+pub mod cluster;
+pub mod config;
+mod error_code;
+pub mod fault;
+pub mod flush;
+pub mod harness;
+pub mod history;
+pub mod interceptor;
 pub mod kv;
+pub mod metrics;
+pub mod node;
 mod node_id;
+mod panic;
+pub mod raft;
+pub mod replay;
+mod request;
+mod rng;
+pub mod rpc;
+pub mod shutdown;
+#[cfg(test)]
+mod testing;
+pub mod transcript;
+pub mod wire;
+pub mod workloads;
 
 pub use client::MaelstromClient;
+pub use cluster::Cluster;
+pub use error_code::{ErrorCode, ErrorPayload};
+pub use flush::FlushPolicy;
+pub use node::Node;
 pub use node_id::NodeId;
+pub use panic::PanicPolicy;
+use rpc::{Rpc, Typed};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -20,6 +52,16 @@ pub enum Error {
     MissingInitMessage,
     #[error("Detached clients cannot read from stdin")]
     DetachedClientCantRead,
+    #[error("Invalid fault configuration: {0}")]
+    InvalidFaultConfig(String),
+    #[error("Impossible quorum: {0}")]
+    ImpossibleQuorum(String),
+    #[error("Invalid node id: {0}")]
+    InvalidNodeId(String),
+    #[error("Invalid parameter: {0}")]
+    InvalidParam(String),
+    #[error("Harness failed: {0}")]
+    Harness(String),
 }
 
 pub struct Message<Payload> {
@@ -48,6 +90,20 @@ impl<T> Message<T> {
     pub fn basic_response<'a>(&self, ty: &'a str) -> Response<BasicResponsePayload<'a>> {
         self.response(BasicResponsePayload { ty })
     }
+
+    /// A reply to `request`, which is only accepted if it is the reply type of the request
+    pub fn reply<R: Rpc>(&self, request: &R, reply: R::Reply) -> Response<Typed<R::Reply>> {
+        let _ = request;
+        self.response(Typed(reply))
+    }
+
+    pub fn error_response(
+        &self,
+        code: ErrorCode,
+        text: impl Into<String>,
+    ) -> Response<ErrorPayload> {
+        self.response(ErrorPayload::new(code, text))
+    }
 }
 
 pub struct Response<Payload> {
@@ -64,18 +120,6 @@ impl<Payload> Response<Payload> {
             payload,
         }
     }
-
-    fn raw(&self, src: NodeId, msg_id: Option<u32>) -> RawResponse<&Payload> {
-        RawResponse {
-            src,
-            dest: self.dest,
-            body: RawResponseBody {
-                msg_id,
-                in_reply_to: self.in_reply_to,
-                payload: &self.payload,
-            },
-        }
-    }
 }
 
 impl<'de, Payload: Deserialize<'de>> Deserialize<'de> for Message<Payload> {
@@ -83,44 +127,6 @@ impl<'de, Payload: Deserialize<'de>> Deserialize<'de> for Message<Payload> {
     where
         D: serde::Deserializer<'de>,
     {
-        #[derive(Deserialize)]
-        pub struct RawMessage<Payload> {
-            pub src: NodeId,
-            pub dest: NodeId,
-            pub body: RawMessageBody<Payload>,
-        }
-
-        #[derive(Deserialize)]
-        pub struct RawMessageBody<Payload> {
-            pub msg_id: Option<u32>,
-            pub in_reply_to: Option<u32>,
-            #[serde(flatten)]
-            pub payload: Payload,
-        }
-
-        let message = RawMessage::<Payload>::deserialize(deserializer)?;
-
-        Ok(Self {
-            src: message.src,
-            dest: message.dest,
-            msg_id: message.body.msg_id,
-            in_reply_to: message.body.in_reply_to,
-            payload: message.body.payload,
-        })
+        wire::deserialize_message(deserializer)
     }
 }
-
-#[derive(Serialize)]
-struct RawResponse<Payload> {
-    pub src: NodeId,
-    pub dest: NodeId,
-    pub body: RawResponseBody<Payload>,
-}
-
-#[derive(Serialize)]
-struct RawResponseBody<Payload> {
-    pub msg_id: Option<u32>,
-    pub in_reply_to: Option<u32>,
-    #[serde(flatten)]
-    pub payload: Payload,
-}
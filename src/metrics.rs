@@ -0,0 +1,349 @@
+//! Message counts and latencies, kept by every [`MaelstromClient`](crate::MaelstromClient)
+//!
+//! The summary is written to stderr as a single line of json when stdin is
+//! closed, or whenever [`MaelstromClient::report_metrics`](crate::MaelstromClient::report_metrics)
+//! is called.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{
+    interceptor::{Flow, Frame, Interceptor},
+    wire, Error, NodeId,
+};
+
+/// Requests that are still unanswered after this long are forgotten
+const MAX_PENDING_AGE: Duration = Duration::from_secs(60);
+const MAX_PENDING: usize = 4096;
+
+/// A cheap handle to a set of counters, shared by every clone
+#[derive(Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    sent: Traffic,
+    received: Traffic,
+    /// Requests we sent, by `msg_id`
+    outgoing: HashMap<u32, (Instant, String)>,
+    /// Requests we received, by sender and `msg_id`
+    incoming: HashMap<(NodeId, u32), (Instant, String)>,
+    round_trip: BTreeMap<String, Histogram>,
+    handling: BTreeMap<String, Histogram>,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Traffic {
+    pub messages: u64,
+    pub bytes: u64,
+    pub by_type: BTreeMap<String, u64>,
+    /// Keyed by `client`, `node` or `service`, the peer being the destination
+    /// for sent messages and the source for received ones
+    pub by_peer: BTreeMap<&'static str, u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub sent: Traffic,
+    pub received: Traffic,
+    /// Time from sending a request to receiving its reply, by request type
+    pub round_trip: BTreeMap<String, LatencySummary>,
+    /// Time from receiving a request to replying to it, by request type
+    pub handling: BTreeMap<String, LatencySummary>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub min_us: u64,
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+/// A histogram with power of two buckets, in microseconds
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    buckets: [u64; 65],
+    count: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: [0; 65],
+            count: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, duration: Duration) {
+        let micros = duration.as_micros().min(u64::MAX.into()) as u64;
+        self.buckets[(u64::BITS - micros.leading_zeros()) as usize] += 1;
+        self.count += 1;
+        self.min = self.min.min(micros);
+        self.max = self.max.max(micros);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// An upper bound for the `q` quantile, within a factor of two
+    pub fn quantile(&self, q: f64) -> u64 {
+        let rank = (q * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let upper = match bucket {
+                    0 => 0,
+                    _ => u64::MAX >> (u64::BITS as usize - bucket),
+                };
+                return upper.clamp(self.min, self.max);
+            }
+        }
+
+        self.max
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            count: self.count,
+            min_us: self.min.min(self.max),
+            p50_us: self.quantile(0.5),
+            p90_us: self.quantile(0.9),
+            p99_us: self.quantile(0.99),
+            max_us: self.max,
+        }
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn summary(&self) -> Summary {
+        let inner = self.inner.lock().unwrap();
+        let summarize = |histograms: &BTreeMap<String, Histogram>| {
+            histograms
+                .iter()
+                .map(|(ty, histogram)| (ty.clone(), histogram.summary()))
+                .collect()
+        };
+
+        Summary {
+            sent: inner.sent.clone(),
+            received: inner.received.clone(),
+            round_trip: summarize(&inner.round_trip),
+            handling: summarize(&inner.handling),
+        }
+    }
+
+    /// Writes the summary to stderr
+    pub fn report(&self, node_id: NodeId) -> Result<(), Error> {
+        #[derive(Serialize)]
+        struct Report {
+            node_id: NodeId,
+            metrics: Summary,
+        }
+
+        let report = serde_json::to_string(&Report {
+            node_id,
+            metrics: self.summary(),
+        })?;
+        eprintln!("{report}");
+
+        Ok(())
+    }
+}
+
+fn peer_class(node: NodeId) -> &'static str {
+    if node.is_client() {
+        "client"
+    } else if node.is_node() {
+        "node"
+    } else {
+        "service"
+    }
+}
+
+impl Traffic {
+    fn record(&mut self, frame: &Frame, ty: &str, peer: NodeId) {
+        self.messages += 1;
+        self.bytes += frame.as_bytes().len() as u64 + 1;
+        match self.by_type.get_mut(ty) {
+            Some(count) => *count += 1,
+            None => {
+                self.by_type.insert(ty.to_owned(), 1);
+            }
+        }
+        *self.by_peer.entry(peer_class(peer)).or_default() += 1;
+    }
+}
+
+impl Inner {
+    fn forget_stale(&mut self, now: Instant) {
+        if self.outgoing.len() > MAX_PENDING {
+            self.outgoing
+                .retain(|_, (sent, _)| now - *sent < MAX_PENDING_AGE);
+        }
+
+        if self.incoming.len() > MAX_PENDING {
+            self.incoming
+                .retain(|_, (received, _)| now - *received < MAX_PENDING_AGE);
+        }
+    }
+}
+
+impl Interceptor for Metrics {
+    fn on_read(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+        let Ok(header) = wire::header(frame.as_bytes()) else {
+            return Ok(Flow::Continue);
+        };
+        let Some(ty) = header.body.ty else {
+            return Ok(Flow::Continue);
+        };
+        let now = Instant::now();
+        let inner = &mut *self.inner.lock().unwrap();
+
+        inner.received.record(frame, &ty, header.src);
+
+        if let Some(in_reply_to) = header.body.in_reply_to {
+            if let Some((sent, ty)) = inner.outgoing.remove(&in_reply_to) {
+                inner.round_trip.entry(ty).or_default().record(now - sent);
+            }
+        } else if let Some(msg_id) = header.body.msg_id {
+            inner
+                .incoming
+                .insert((header.src, msg_id), (now, ty.into_owned()));
+            inner.forget_stale(now);
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    fn on_write(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+        let Ok(header) = wire::header(frame.as_bytes()) else {
+            return Ok(Flow::Continue);
+        };
+        let Some(ty) = header.body.ty else {
+            return Ok(Flow::Continue);
+        };
+        let now = Instant::now();
+        let inner = &mut *self.inner.lock().unwrap();
+
+        inner.sent.record(frame, &ty, header.dest);
+
+        if let Some(in_reply_to) = header.body.in_reply_to {
+            if let Some((received, ty)) = inner.incoming.remove(&(header.dest, in_reply_to)) {
+                inner.handling.entry(ty).or_default().record(now - received);
+            }
+        } else if let Some(msg_id) = header.body.msg_id {
+            inner.outgoing.insert(msg_id, (now, ty.into_owned()));
+            inner.forget_stale(now);
+        }
+
+        Ok(Flow::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(micros: impl IntoIterator<Item = u64>) -> Histogram {
+        let mut histogram = Histogram::default();
+        for micros in micros {
+            histogram.record(Duration::from_micros(micros));
+        }
+        histogram
+    }
+
+    #[test]
+    fn buckets_are_powers_of_two() {
+        // the upper bound of the bucket, clamped to what was recorded
+        let bound = |micros: u64| histogram([0, micros, u64::MAX]).quantile(0.5);
+
+        assert_eq!(bound(0), 0);
+        assert_eq!(bound(1), 1);
+        assert_eq!(bound(2), 3);
+        assert_eq!(bound(3), 3);
+        assert_eq!(bound(4), 7);
+        assert_eq!(bound(1023), 1023);
+        assert_eq!(bound(1024), 2047);
+        assert_eq!(bound(u64::MAX), u64::MAX);
+
+        let histogram = histogram([1, 2, 3, 4]);
+        assert_eq!(histogram.buckets[..4], [0, 1, 2, 1]);
+    }
+
+    #[test]
+    fn quantiles_of_a_known_sample() {
+        let summary = histogram(1..=100).summary();
+
+        assert_eq!(
+            summary,
+            LatencySummary {
+                count: 100,
+                min_us: 1,
+                // the 50th value is in the bucket of 32 to 63
+                p50_us: 63,
+                p90_us: 100,
+                p99_us: 100,
+                max_us: 100,
+            }
+        );
+    }
+
+    #[test]
+    fn empty_histograms_are_zero() {
+        let summary = Histogram::default().summary();
+
+        assert_eq!(summary.count, 0);
+        assert_eq!((summary.min_us, summary.p50_us, summary.max_us), (0, 0, 0));
+    }
+
+    #[test]
+    fn counts_traffic_and_round_trips() {
+        let mut metrics = Metrics::new();
+        let frame = |json: &str| Frame::new(json.as_bytes().to_vec());
+
+        let request = r#"{"src":"n1","dest":"n2","body":{"type":"read","msg_id":1}}"#;
+        let reply = r#"{"src":"n2","dest":"n1","body":{"type":"read_ok","in_reply_to":1}}"#;
+        assert_eq!(
+            metrics.on_write(&mut frame(request)).unwrap(),
+            Flow::Continue
+        );
+        assert_eq!(metrics.on_read(&mut frame(reply)).unwrap(), Flow::Continue);
+        // frames without a type are not counted
+        let untyped = r#"{"src":"c1","dest":"n1","body":{"msg_id":1}}"#;
+        assert_eq!(
+            metrics.on_read(&mut frame(untyped)).unwrap(),
+            Flow::Continue
+        );
+
+        let summary = metrics.summary();
+        assert_eq!(summary.sent.messages, 1);
+        assert_eq!(summary.sent.bytes, request.len() as u64 + 1);
+        assert_eq!(summary.received.by_type["read_ok"], 1);
+        assert_eq!(summary.received.by_peer["node"], 1);
+        assert_eq!(summary.round_trip["read"].count, 1);
+        assert!(summary.handling.is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+//! A single threaded event loop for nodes that need timers
+//!
+//! Instead of spawning a background thread with a detached client and sharing
+//! state behind a mutex, a [`Node`] gets all of its messages and timer ticks
+//! on one thread, in a well defined order. That order is also what a
+//! transcript records, which makes nodes written this way replayable, see
+//! [`crate::replay`].
+
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+
+use crate::{client::Incoming, Error, MaelstromClient, Message};
+
+pub trait Node {
+    type Payload: DeserializeOwned;
+
+    fn handle(
+        &mut self,
+        client: &mut MaelstromClient,
+        message: Message<Self::Payload>,
+    ) -> Result<(), Error>;
+
+    /// How often [`Node::tick`] is called, `None` if the node has no timers
+    fn tick_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    fn tick(&mut self, client: &mut MaelstromClient) -> Result<(), Error> {
+        let _ = client;
+        Ok(())
+    }
+}
+
+/// Does the init handshake on stdin and stdout, and runs the node returned by
+/// `init` until it is shut down
+pub fn run<N: Node>(init: impl FnOnce(&MaelstromClient) -> N) -> Result<(), Error> {
+    let client = MaelstromClient::new()?;
+    let node = init(&client);
+
+    run_with(client, node)
+}
+
+/// Runs a node on an already initialized client until it is shut down, see [`crate::shutdown`]
+pub fn run_with<N: Node>(mut client: MaelstromClient, mut node: N) -> Result<(), Error> {
+    client.spawn_reader();
+
+    let mut next_tick = node
+        .tick_interval()
+        .map(|interval| Instant::now() + interval);
+
+    loop {
+        match client.next_incoming(next_tick)? {
+            Incoming::Message => {
+                if let Some(message) = client.incoming::<N::Payload>()? {
+                    let (src, msg_id) = (message.src, message.msg_id);
+                    client.catch_panic(src, msg_id, |client| node.handle(client, message))?;
+                }
+
+                if next_tick.is_none() {
+                    next_tick = node
+                        .tick_interval()
+                        .map(|interval| Instant::now() + interval);
+                }
+            }
+            Incoming::Timeout => {
+                client.notify_tick()?;
+                node.tick(&mut client)?;
+
+                next_tick = node.tick_interval().map(|interval| {
+                    let now = Instant::now();
+                    // skip ticks we fell behind on instead of firing them in a burst
+                    next_tick.map_or(now, |tick| tick.max(now)) + interval
+                });
+            }
+            Incoming::Eof => break,
+        }
+    }
+
+    client.shut_down()
+}
@@ -1,7 +1,9 @@
-use std::fmt::Debug;
+use std::{fmt, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 
+use crate::Error;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct NodeId {
     imp: NodeIdImp,
@@ -12,6 +14,7 @@ enum NodeIdImp {
     Maelstrom(u32),
     Node(u32),
     SeqKv,
+    LinKv,
 }
 
 impl NodeId {
@@ -25,24 +28,55 @@ impl NodeId {
         matches!(self.imp, NodeIdImp::SeqKv)
     }
 
+    pub fn lin_kv() -> Self {
+        Self {
+            imp: NodeIdImp::LinKv,
+        }
+    }
+
+    pub fn is_lin_kv(self) -> bool {
+        matches!(self.imp, NodeIdImp::LinKv)
+    }
+
+    /// The node `n<value>`
+    pub fn node(value: u32) -> Self {
+        Self {
+            imp: NodeIdImp::Node(value),
+        }
+    }
+
+    /// The maelstrom client `c<value>`
+    pub fn client(value: u32) -> Self {
+        Self {
+            imp: NodeIdImp::Maelstrom(value),
+        }
+    }
+
+    pub fn is_client(self) -> bool {
+        matches!(self.imp, NodeIdImp::Maelstrom(_))
+    }
+
+    pub fn is_node(self) -> bool {
+        matches!(self.imp, NodeIdImp::Node(_))
+    }
+
     pub fn value(self) -> u32 {
         match self.imp {
             NodeIdImp::Maelstrom(value) | NodeIdImp::Node(value) => value,
-            NodeIdImp::SeqKv => u32::MAX,
+            NodeIdImp::SeqKv | NodeIdImp::LinKv => u32::MAX,
         }
     }
 }
 
-impl Serialize for NodeId {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
+impl NodeId {
+    /// Calls `f` with the id as it is written in messages, without allocating
+    pub(crate) fn with_str<R>(self, f: impl FnOnce(&str) -> R) -> R {
         let mut output = [0u8; 1 + core::mem::size_of::<itoa::Buffer>()];
         output[0] = match self.imp {
             NodeIdImp::Maelstrom(_) => b'c',
             NodeIdImp::Node(_) => b'n',
-            NodeIdImp::SeqKv => return "seq-kv".serialize(serializer),
+            NodeIdImp::SeqKv => return f("seq-kv"),
+            NodeIdImp::LinKv => return f("lin-kv"),
         };
         let mut buf = itoa::Buffer::new();
         let s = buf.format(self.value());
@@ -50,7 +84,60 @@ impl Serialize for NodeId {
         output[1..][..s.len()].copy_from_slice(s.as_bytes());
         let value = unsafe { core::str::from_utf8_unchecked(&output[..1 + s.len()]) };
 
-        value.serialize(serializer)
+        f(value)
+    }
+}
+
+/// The id as it is written in messages, like `n1`, `c12` or `seq-kv`
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.with_str(|id| f.pad(id))
+    }
+}
+
+impl FromStr for NodeId {
+    type Err = Error;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        let imp = match id {
+            "seq-kv" => NodeIdImp::SeqKv,
+            "lin-kv" => NodeIdImp::LinKv,
+            _ => {
+                let number = |rest: &str| {
+                    // `u32::from_str` also takes a leading `+`
+                    rest.bytes()
+                        .all(|byte| byte.is_ascii_digit())
+                        .then(|| rest.parse::<u32>().ok())
+                        .flatten()
+                        .ok_or_else(|| {
+                            Error::InvalidNodeId(format!(
+                                "`{id}` should be followed by a number that fits in 32 bits"
+                            ))
+                        })
+                };
+
+                match id.split_at_checked(1) {
+                    Some(("n", rest)) => NodeIdImp::Node(number(rest)?),
+                    Some(("c", rest)) => NodeIdImp::Maelstrom(number(rest)?),
+                    _ => {
+                        return Err(Error::InvalidNodeId(format!(
+                            "expected `n<number>`, `c<number>`, `seq-kv` or `lin-kv`, got `{id}`"
+                        )))
+                    }
+                }
+            }
+        };
+
+        Ok(Self { imp })
+    }
+}
+
+impl Serialize for NodeId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.with_str(|id| id.serialize(serializer))
     }
 }
 
@@ -64,7 +151,7 @@ impl<'de> Deserialize<'de> for NodeId {
         impl<'de> serde::de::Visitor<'de> for NodeIdVisitor {
             type Value = NodeId;
 
-            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
                 write!(formatter, "a maelstrom node id")
             }
 
@@ -72,34 +159,70 @@ impl<'de> Deserialize<'de> for NodeId {
             where
                 E: serde::de::Error,
             {
-                if let Some(rest) = v.strip_prefix(['c', 'n']) {
-                    let is_maelstrom_client = v.as_bytes()[0] == b'c';
-                    let id = rest.parse::<u32>().map_err(|_| {
-                        serde::de::Error::invalid_type(
-                            serde::de::Unexpected::Str(v),
-                            &"a maelstrtom node id",
-                        )
-                    })?;
-                    Ok(NodeId {
-                        imp: if is_maelstrom_client {
-                            NodeIdImp::Maelstrom(id)
-                        } else {
-                            NodeIdImp::Node(id)
-                        },
-                    })
-                } else if v == "seq-kv" {
-                    Ok(NodeId {
-                        imp: NodeIdImp::SeqKv,
-                    })
-                } else {
-                    Err(serde::de::Error::invalid_type(
-                        serde::de::Unexpected::Str(v),
-                        &"a node type either `c` or `n`",
-                    ))
-                }
+                v.parse().map_err(E::custom)
             }
         }
 
         deserializer.deserialize_str(NodeIdVisitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let ids = [
+            ("n3", NodeId::node(3)),
+            ("c12", NodeId::client(12)),
+            ("n0", NodeId::node(0)),
+            ("n4294967295", NodeId::node(u32::MAX)),
+            ("seq-kv", NodeId::seq_kv()),
+            ("lin-kv", NodeId::lin_kv()),
+        ];
+
+        for (text, id) in ids {
+            assert_eq!(text.parse::<NodeId>().unwrap(), id);
+            assert_eq!(id.to_string(), text);
+
+            let json = serde_json::to_string(&id).unwrap();
+            assert_eq!(json, format!("\"{text}\""));
+            assert_eq!(serde_json::from_str::<NodeId>(&json).unwrap(), id);
+        }
+    }
+
+    #[test]
+    fn invalid_ids() {
+        let invalid = [
+            "",
+            "n",
+            "c",
+            "x1",
+            "n-1",
+            "n+1",
+            "n 1",
+            "n1x",
+            "N1",
+            "n4294967296",
+            "seq_kv",
+        ];
+
+        for text in invalid {
+            assert!(
+                matches!(text.parse::<NodeId>(), Err(Error::InvalidNodeId(_))),
+                "{text:?}"
+            );
+            assert!(serde_json::from_str::<NodeId>(&format!("\"{text}\"")).is_err());
+        }
+    }
+
+    #[test]
+    fn kinds() {
+        assert!(NodeId::node(1).is_node() && !NodeId::node(1).is_client());
+        assert!(NodeId::client(1).is_client() && !NodeId::client(1).is_node());
+        assert!(NodeId::seq_kv().is_seq_kv() && !NodeId::seq_kv().is_lin_kv());
+        assert_eq!(NodeId::client(12).value(), 12);
+        assert_eq!(format!("{:>4}", NodeId::node(3)), "  n3");
+    }
+}
@@ -0,0 +1,54 @@
+use std::{
+    any::Any,
+    backtrace::{Backtrace, BacktraceStatus},
+    cell::Cell,
+    panic::AssertUnwindSafe,
+    sync::Once,
+};
+
+/// What [`MaelstromClient::catch_panic`](crate::MaelstromClient::catch_panic)
+/// does after replying to a panicking handler
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanicPolicy {
+    /// Keep handling messages
+    #[default]
+    Continue,
+    /// Resume the panic, which takes the node down
+    Exit,
+}
+
+thread_local! {
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f`, logging a backtrace if it panics
+pub(crate) fn catch<T>(f: impl FnOnce() -> T) -> Result<T, Box<dyn Any + Send>> {
+    static HOOK: Once = Once::new();
+
+    HOOK.call_once(|| {
+        let default = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            default(info);
+
+            // the default hook already printed one if RUST_BACKTRACE is set
+            if CATCHING.get() && Backtrace::capture().status() != BacktraceStatus::Captured {
+                eprintln!("stack backtrace:\n{}", Backtrace::force_capture());
+            }
+        }));
+    });
+
+    let catching = CATCHING.replace(true);
+    let result = std::panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.set(catching);
+
+    result
+}
+
+/// The message passed to `panic!`, if it was a string
+pub(crate) fn message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>")
+}
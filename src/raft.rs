@@ -0,0 +1,1123 @@
+//! Leader based consensus on top of [`MaelstromClient`]
+//!
+//! Every node keeps a replicated log of client requests, the elected leader
+//! appends to it and replicates it to the rest of the cluster, and once an
+//! entry is stored on a majority it is committed and handed to the
+//! [`StateMachine`] on every node, in log order.
+//!
+//! Client requests that reach a follower are proxied to the current leader, if
+//! no leader is known the client gets a `temporarily-unavailable` (11) error.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    node::{self, Node},
+    rng::Rng,
+    Error, ErrorCode, ErrorPayload, MaelstromClient, Message, NodeId, Response,
+};
+
+/// How often [`run`] ticks the raft node
+pub const TICK: Duration = Duration::from_millis(10);
+
+const HEARTBEAT_TICKS: u32 = 5;
+const MIN_ELECTION_TICKS: u64 = 30;
+const MAX_ELECTION_TICKS: u64 = 60;
+const PROXY_TIMEOUT_TICKS: u32 = 100;
+const MAX_ENTRIES_PER_APPEND: usize = 64;
+
+pub trait StateMachine {
+    type Request: Serialize + DeserializeOwned + Clone;
+    type Response: Serialize;
+
+    /// Called exactly once per committed request on every node, in log order
+    ///
+    /// The result is sent back to the client that issued the request
+    fn apply(&mut self, request: &Self::Request) -> Result<Self::Response, ErrorPayload>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry<R> {
+    pub term: u64,
+    /// `None` for the no-op entry a leader appends when it is elected
+    pub request: Option<R>,
+}
+
+/// Everything a raft node can receive, either traffic between raft peers or
+/// a client request for the state machine
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Payload<R> {
+    Raft(RaftPayload<R>),
+    Client(R),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum RaftPayload<R> {
+    RequestVote {
+        term: u64,
+        last_log_index: usize,
+        last_log_term: u64,
+    },
+    RequestVoteResult {
+        term: u64,
+        vote_granted: bool,
+    },
+    AppendEntries {
+        term: u64,
+        prev_log_index: usize,
+        prev_log_term: u64,
+        entries: Vec<Entry<R>>,
+        leader_commit: usize,
+    },
+    AppendEntriesResult {
+        term: u64,
+        success: bool,
+        match_index: usize,
+    },
+    Forward {
+        proxy_id: u32,
+        request: R,
+    },
+    ForwardResult {
+        proxy_id: u32,
+        body: serde_json::Value,
+    },
+}
+
+enum Role {
+    Follower,
+    Candidate {
+        votes: HashSet<NodeId>,
+    },
+    Leader {
+        next_index: HashMap<NodeId, usize>,
+        match_index: HashMap<NodeId, usize>,
+    },
+}
+
+/// Where the reply for a log entry has to go once it is applied
+enum Origin {
+    Client {
+        dest: NodeId,
+        in_reply_to: Option<u32>,
+    },
+    Proxy {
+        dest: NodeId,
+        proxy_id: u32,
+    },
+}
+
+struct Pending {
+    term: u64,
+    origin: Origin,
+}
+
+/// A client request this node forwarded to the leader
+struct Proxy {
+    dest: NodeId,
+    in_reply_to: Option<u32>,
+    elapsed: u32,
+}
+
+pub struct Raft<S: StateMachine> {
+    node_id: NodeId,
+    peers: Vec<NodeId>,
+    majority: usize,
+    state_machine: S,
+
+    role: Role,
+    term: u64,
+    voted_for: Option<NodeId>,
+    leader: Option<NodeId>,
+
+    log: Vec<Entry<S::Request>>,
+    commit_index: usize,
+    last_applied: usize,
+
+    rng: Rng,
+    election_elapsed: u32,
+    election_timeout: u32,
+    heartbeat_elapsed: u32,
+
+    pending: BTreeMap<usize, Pending>,
+    proxies: HashMap<u32, Proxy>,
+    next_proxy_id: u32,
+}
+
+impl<S: StateMachine> Raft<S> {
+    pub fn new(client: &MaelstromClient, state_machine: S) -> Self {
+        let node_id = client.node_id();
+        let mut raft = Self {
+            node_id,
+            peers: client.cluster().peers().collect(),
+            majority: client.cluster().majority(),
+            state_machine,
+
+            role: Role::Follower,
+            term: 0,
+            voted_for: None,
+            leader: None,
+
+            log: Vec::new(),
+            commit_index: 0,
+            last_applied: 0,
+
+            rng: Rng::new(node_id.value().into()),
+            election_elapsed: 0,
+            election_timeout: 0,
+            heartbeat_elapsed: 0,
+
+            pending: BTreeMap::new(),
+            proxies: HashMap::new(),
+            next_proxy_id: 0,
+        };
+
+        raft.reset_election_timer();
+
+        raft
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    pub fn leader(&self) -> Option<NodeId> {
+        self.leader
+    }
+
+    pub fn is_leader(&self) -> bool {
+        matches!(self.role, Role::Leader { .. })
+    }
+
+    /// The index of the last entry known to be stored on a majority of nodes
+    pub fn commit_index(&self) -> usize {
+        self.commit_index
+    }
+
+    pub fn state_machine(&self) -> &S {
+        &self.state_machine
+    }
+
+    fn handle_client(
+        &mut self,
+        client: &mut MaelstromClient,
+        src: NodeId,
+        msg_id: Option<u32>,
+        request: S::Request,
+    ) -> Result<(), Error> {
+        let origin = Origin::Client {
+            dest: src,
+            in_reply_to: msg_id,
+        };
+
+        if self.is_leader() {
+            return self.propose(client, origin, request);
+        }
+
+        let Some(leader) = self.leader else {
+            return self.reply(
+                client,
+                origin,
+                ErrorPayload::new(ErrorCode::TEMPORARILY_UNAVAILABLE, "there is no leader"),
+            );
+        };
+
+        let proxy_id = self.next_proxy_id;
+        self.next_proxy_id = self.next_proxy_id.wrapping_add(1);
+        self.proxies.insert(
+            proxy_id,
+            Proxy {
+                dest: src,
+                in_reply_to: msg_id,
+                elapsed: 0,
+            },
+        );
+
+        self.send(client, leader, RaftPayload::Forward { proxy_id, request })
+    }
+
+    fn handle_raft(
+        &mut self,
+        client: &mut MaelstromClient,
+        src: NodeId,
+        payload: RaftPayload<S::Request>,
+    ) -> Result<(), Error> {
+        match payload {
+            RaftPayload::RequestVote {
+                term,
+                last_log_index,
+                last_log_term,
+            } => {
+                self.observe_term(term);
+
+                let vote_granted = term == self.term
+                    && self.voted_for.unwrap_or(src) == src
+                    && (last_log_term, last_log_index) >= (self.last_log_term(), self.log.len());
+
+                if vote_granted {
+                    self.voted_for = Some(src);
+                    self.reset_election_timer();
+                }
+
+                self.send(
+                    client,
+                    src,
+                    RaftPayload::RequestVoteResult {
+                        term: self.term,
+                        vote_granted,
+                    },
+                )
+            }
+            RaftPayload::RequestVoteResult { term, vote_granted } => {
+                self.observe_term(term);
+
+                if term != self.term || !vote_granted {
+                    return Ok(());
+                }
+
+                let Role::Candidate { votes } = &mut self.role else {
+                    return Ok(());
+                };
+
+                votes.insert(src);
+                if votes.len() >= self.majority {
+                    self.become_leader(client)?;
+                }
+
+                Ok(())
+            }
+            RaftPayload::AppendEntries {
+                term,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => {
+                self.observe_term(term);
+
+                if term < self.term {
+                    return self.send(
+                        client,
+                        src,
+                        RaftPayload::AppendEntriesResult {
+                            term: self.term,
+                            success: false,
+                            match_index: 0,
+                        },
+                    );
+                }
+
+                self.role = Role::Follower;
+                self.leader = Some(src);
+                self.reset_election_timer();
+
+                if prev_log_index > self.log.len() || self.term_at(prev_log_index) != prev_log_term
+                {
+                    let match_index = self.log.len().min(prev_log_index.saturating_sub(1));
+                    return self.send(
+                        client,
+                        src,
+                        RaftPayload::AppendEntriesResult {
+                            term: self.term,
+                            success: false,
+                            match_index,
+                        },
+                    );
+                }
+
+                let match_index = prev_log_index + entries.len();
+                for (index, entry) in (prev_log_index + 1..).zip(entries) {
+                    if index <= self.log.len() {
+                        if self.term_at(index) == entry.term {
+                            continue;
+                        }
+
+                        self.log.truncate(index - 1);
+                    }
+
+                    self.log.push(entry);
+                }
+
+                // a stale append can match less than is already committed
+                let commit_index = self.commit_index.max(leader_commit.min(match_index));
+                if commit_index > self.commit_index {
+                    self.commit_index = commit_index;
+                    self.apply(client)?;
+                }
+
+                self.send(
+                    client,
+                    src,
+                    RaftPayload::AppendEntriesResult {
+                        term: self.term,
+                        success: true,
+                        match_index,
+                    },
+                )
+            }
+            RaftPayload::AppendEntriesResult {
+                term,
+                success,
+                match_index: peer_match_index,
+            } => {
+                self.observe_term(term);
+
+                if term != self.term {
+                    return Ok(());
+                }
+
+                let Role::Leader {
+                    next_index,
+                    match_index,
+                } = &mut self.role
+                else {
+                    return Ok(());
+                };
+
+                if success {
+                    let match_index = match_index.entry(src).or_default();
+                    *match_index = peer_match_index.max(*match_index);
+                    next_index.insert(src, *match_index + 1);
+                    self.advance_commit(client)
+                } else {
+                    let next_index = next_index.entry(src).or_insert(1);
+                    *next_index = (*next_index - 1).min(peer_match_index + 1).max(1);
+                    self.send_append(client, src)
+                }
+            }
+            RaftPayload::Forward { proxy_id, request } => {
+                let origin = Origin::Proxy {
+                    dest: src,
+                    proxy_id,
+                };
+
+                if self.is_leader() {
+                    self.propose(client, origin, request)
+                } else {
+                    self.reply(
+                        client,
+                        origin,
+                        ErrorPayload::new(ErrorCode::TEMPORARILY_UNAVAILABLE, "not the leader"),
+                    )
+                }
+            }
+            RaftPayload::ForwardResult { proxy_id, body } => {
+                let Some(proxy) = self.proxies.remove(&proxy_id) else {
+                    return Ok(());
+                };
+
+                client.write_no_response(Response {
+                    dest: proxy.dest,
+                    in_reply_to: proxy.in_reply_to,
+                    payload: body,
+                })
+            }
+        }
+    }
+
+    fn term_at(&self, index: usize) -> u64 {
+        match index {
+            0 => 0,
+            _ => self.log[index - 1].term,
+        }
+    }
+
+    fn last_log_term(&self) -> u64 {
+        self.term_at(self.log.len())
+    }
+
+    fn reset_election_timer(&mut self) {
+        self.election_elapsed = 0;
+        self.election_timeout = self.rng.range(MIN_ELECTION_TICKS, MAX_ELECTION_TICKS) as u32;
+    }
+
+    /// Steps down if a peer is in a newer term
+    fn observe_term(&mut self, term: u64) {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+            self.leader = None;
+            self.role = Role::Follower;
+        }
+    }
+
+    fn start_election(&mut self, client: &mut MaelstromClient) -> Result<(), Error> {
+        self.term += 1;
+        self.voted_for = Some(self.node_id);
+        self.leader = None;
+        self.role = Role::Candidate {
+            votes: HashSet::from([self.node_id]),
+        };
+        self.reset_election_timer();
+
+        if self.majority == 1 {
+            return self.become_leader(client);
+        }
+
+        for i in 0..self.peers.len() {
+            self.send(
+                client,
+                self.peers[i],
+                RaftPayload::RequestVote {
+                    term: self.term,
+                    last_log_index: self.log.len(),
+                    last_log_term: self.last_log_term(),
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn become_leader(&mut self, client: &mut MaelstromClient) -> Result<(), Error> {
+        let next = self.log.len() + 1;
+        self.role = Role::Leader {
+            next_index: self.peers.iter().map(|&peer| (peer, next)).collect(),
+            match_index: self.peers.iter().map(|&peer| (peer, 0)).collect(),
+        };
+        self.leader = Some(self.node_id);
+        self.heartbeat_elapsed = 0;
+
+        // entries from earlier terms can only be committed indirectly,
+        // so commit something in our own term as soon as possible
+        self.log.push(Entry {
+            term: self.term,
+            request: None,
+        });
+
+        self.broadcast_append(client)?;
+        self.advance_commit(client)
+    }
+
+    fn propose(
+        &mut self,
+        client: &mut MaelstromClient,
+        origin: Origin,
+        request: S::Request,
+    ) -> Result<(), Error> {
+        self.log.push(Entry {
+            term: self.term,
+            request: Some(request),
+        });
+        self.pending.insert(
+            self.log.len(),
+            Pending {
+                term: self.term,
+                origin,
+            },
+        );
+
+        self.broadcast_append(client)?;
+        self.advance_commit(client)
+    }
+
+    fn broadcast_append(&mut self, client: &mut MaelstromClient) -> Result<(), Error> {
+        for i in 0..self.peers.len() {
+            self.send_append(client, self.peers[i])?;
+        }
+
+        Ok(())
+    }
+
+    fn send_append(&mut self, client: &mut MaelstromClient, peer: NodeId) -> Result<(), Error> {
+        let Role::Leader { next_index, .. } = &self.role else {
+            return Ok(());
+        };
+
+        let prev_log_index = next_index.get(&peer).copied().unwrap_or(1) - 1;
+        let end = self.log.len().min(prev_log_index + MAX_ENTRIES_PER_APPEND);
+
+        self.send(
+            client,
+            peer,
+            RaftPayload::AppendEntries {
+                term: self.term,
+                prev_log_index,
+                prev_log_term: self.term_at(prev_log_index),
+                entries: self.log[prev_log_index..end].to_vec(),
+                leader_commit: self.commit_index,
+            },
+        )
+    }
+
+    fn advance_commit(&mut self, client: &mut MaelstromClient) -> Result<(), Error> {
+        let Role::Leader { match_index, .. } = &self.role else {
+            return Ok(());
+        };
+
+        let majority = self.majority;
+        let committed = (self.commit_index + 1..=self.log.len())
+            .rev()
+            .take_while(|&index| self.term_at(index) == self.term)
+            .find(|&index| 1 + match_index.values().filter(|&&m| m >= index).count() >= majority);
+
+        if let Some(index) = committed {
+            self.commit_index = index;
+            self.apply(client)?;
+        }
+
+        Ok(())
+    }
+
+    fn apply(&mut self, client: &mut MaelstromClient) -> Result<(), Error> {
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+
+            let entry = &self.log[self.last_applied - 1];
+            let term = entry.term;
+            let result = entry
+                .request
+                .as_ref()
+                .map(|request| self.state_machine.apply(request));
+
+            let Some(pending) = self.pending.remove(&self.last_applied) else {
+                continue;
+            };
+
+            match result {
+                _ if pending.term != term => self.reply(
+                    client,
+                    pending.origin,
+                    ErrorPayload::new(
+                        ErrorCode::TEMPORARILY_UNAVAILABLE,
+                        "the request was overwritten by another leader",
+                    ),
+                )?,
+                Some(Ok(response)) => self.reply(client, pending.origin, response)?,
+                Some(Err(error)) => self.reply(client, pending.origin, error)?,
+                None => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn reply<T: Serialize>(
+        &mut self,
+        client: &mut MaelstromClient,
+        origin: Origin,
+        payload: T,
+    ) -> Result<(), Error> {
+        match origin {
+            Origin::Client { dest, in_reply_to } => client.write_no_response(Response {
+                dest,
+                in_reply_to,
+                payload,
+            }),
+            Origin::Proxy { dest, proxy_id } => self.send(
+                client,
+                dest,
+                RaftPayload::ForwardResult {
+                    proxy_id,
+                    body: serde_json::to_value(payload)?,
+                },
+            ),
+        }
+    }
+
+    fn send(
+        &self,
+        client: &mut MaelstromClient,
+        dest: NodeId,
+        payload: RaftPayload<S::Request>,
+    ) -> Result<(), Error> {
+        client.write_no_response(Response {
+            dest,
+            in_reply_to: None,
+            payload,
+        })
+    }
+}
+
+impl<S: StateMachine> Node for Raft<S> {
+    type Payload = Payload<S::Request>;
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(TICK)
+    }
+
+    /// Drives elections, heartbeats and proxy timeouts
+    fn tick(&mut self, client: &mut MaelstromClient) -> Result<(), Error> {
+        let mut expired = Vec::new();
+        self.proxies.retain(|_, proxy| {
+            proxy.elapsed += 1;
+            let keep = proxy.elapsed < PROXY_TIMEOUT_TICKS;
+            if !keep {
+                expired.push((proxy.dest, proxy.in_reply_to));
+            }
+            keep
+        });
+
+        for (dest, in_reply_to) in expired {
+            client.write_no_response(Response {
+                dest,
+                in_reply_to,
+                payload: ErrorPayload::new(ErrorCode::TIMEOUT, "the leader did not reply in time"),
+            })?;
+        }
+
+        if self.is_leader() {
+            self.heartbeat_elapsed += 1;
+            if self.heartbeat_elapsed >= HEARTBEAT_TICKS {
+                self.heartbeat_elapsed = 0;
+                self.broadcast_append(client)?;
+            }
+        } else {
+            self.election_elapsed += 1;
+            if self.election_elapsed >= self.election_timeout {
+                self.start_election(client)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle(
+        &mut self,
+        client: &mut MaelstromClient,
+        message: Message<Payload<S::Request>>,
+    ) -> Result<(), Error> {
+        match message.payload {
+            Payload::Client(request) => {
+                self.handle_client(client, message.src, message.msg_id, request)
+            }
+            Payload::Raft(payload) => self.handle_raft(client, message.src, payload),
+        }
+    }
+}
+
+/// Runs a raft node until it is shut down
+pub fn run<S: StateMachine>(client: MaelstromClient, state_machine: S) -> Result<(), Error> {
+    let raft = Raft::new(&client, state_machine);
+    node::run_with(client, raft)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        checker::linearizable,
+        history::{Event, EventKind},
+        testing::{self, Wire},
+        workloads::lin_kv::Kv,
+    };
+
+    struct SimNode {
+        raft: Raft<Kv>,
+        client: MaelstromClient,
+        wire: Wire,
+    }
+
+    impl SimNode {
+        fn new(id: u32, count: u32) -> Self {
+            let (client, wire) = testing::client(id, count, &[]);
+
+            Self {
+                raft: Raft::new(&client, Kv::default()),
+                client,
+                wire,
+            }
+        }
+
+        fn deliver(&mut self, frame: &[u8]) {
+            let message = serde_json::from_slice(frame).unwrap();
+            self.raft.handle(&mut self.client, message).unwrap();
+        }
+
+        fn append(&mut self, prev_log_index: usize, entries: usize, leader_commit: usize) {
+            let message = Message {
+                src: NodeId::node(0),
+                dest: self.raft.node_id,
+                msg_id: None,
+                in_reply_to: None,
+                payload: Payload::Raft(RaftPayload::AppendEntries {
+                    term: 1,
+                    prev_log_index,
+                    prev_log_term: if prev_log_index == 0 { 0 } else { 1 },
+                    entries: vec![
+                        Entry {
+                            term: 1,
+                            request: None,
+                        };
+                        entries
+                    ],
+                    leader_commit,
+                }),
+            };
+            self.raft.handle(&mut self.client, message).unwrap();
+        }
+    }
+
+    /// A request a client is waiting on
+    struct InFlight {
+        msg_id: u32,
+        sent: u64,
+    }
+
+    /// Raft nodes on a network that drops, duplicates, delays and reorders
+    /// messages between them, and partitions them, all decided by a seed
+    ///
+    /// Clients send lin-kv requests to random nodes and get replies reliably, and
+    /// every step checks the safety properties of raft
+    struct Simulation {
+        rng: Rng,
+        nodes: Vec<SimNode>,
+        tick: u64,
+        /// Messages between nodes, with the tick they are delivered at
+        network: Vec<(u64, usize, Vec<u8>)>,
+        /// The side of the partition every node is on, all zero if there is none
+        sides: Vec<u32>,
+
+        clients: Vec<Option<InFlight>>,
+        next_msg_id: u32,
+        events: Vec<Event>,
+
+        leaders: HashMap<u64, NodeId>,
+        commits: Vec<usize>,
+    }
+
+    impl Simulation {
+        const DROP: f64 = 0.1;
+        const DUPLICATE: f64 = 0.05;
+        const MAX_DELAY: u64 = 8;
+        const CLIENT_TIMEOUT: u64 = 300;
+
+        fn new(seed: u64, count: u32, clients: usize) -> Self {
+            Self {
+                rng: Rng::new(seed),
+                nodes: (0..count).map(|id| SimNode::new(id, count)).collect(),
+                tick: 0,
+                network: Vec::new(),
+                sides: vec![0; count as usize],
+                clients: (0..clients).map(|_| None).collect(),
+                next_msg_id: 0,
+                events: Vec::new(),
+                leaders: HashMap::new(),
+                commits: vec![0; count as usize],
+            }
+        }
+
+        /// Microseconds, 10ms per tick like [`TICK`], and unique within a tick
+        fn now(&self) -> u64 {
+            self.tick * 10_000 + self.events.len() as u64
+        }
+
+        fn partition(&mut self) {
+            for side in &mut self.sides {
+                *side = self.rng.range(0, 2) as u32;
+            }
+        }
+
+        fn heal(&mut self) {
+            self.sides.fill(0);
+        }
+
+        fn step(&mut self, invoke: bool) {
+            self.tick += 1;
+
+            for node in &mut self.nodes {
+                node.raft.tick(&mut node.client).unwrap();
+            }
+            for index in 0..self.nodes.len() {
+                self.send(index);
+            }
+
+            let (mut due, later) = std::mem::take(&mut self.network)
+                .into_iter()
+                .partition(|&(at, _, _)| at <= self.tick);
+            self.network = later;
+
+            // deliver what is due in a random order
+            let due: &mut Vec<(u64, usize, Vec<u8>)> = &mut due;
+            while !due.is_empty() {
+                let (_, dest, frame) =
+                    due.swap_remove(self.rng.range(0, due.len() as u64) as usize);
+                self.nodes[dest].deliver(&frame);
+                self.send(dest);
+            }
+
+            if invoke {
+                self.invoke();
+            }
+            self.expire();
+            self.check();
+        }
+
+        /// Routes everything a node wrote
+        fn send(&mut self, index: usize) {
+            for frame in self.nodes[index].wire.frames() {
+                let header = crate::wire::header(&frame).unwrap();
+                let dest = header.dest;
+
+                if dest.is_client() {
+                    let in_reply_to = header.body.in_reply_to;
+                    self.complete(dest, in_reply_to, &frame);
+                    continue;
+                }
+
+                let dest = dest.value() as usize;
+                if self.sides[index] != self.sides[dest] || self.rng.chance(Self::DROP) {
+                    continue;
+                }
+
+                let copies = if self.rng.chance(Self::DUPLICATE) {
+                    2
+                } else {
+                    1
+                };
+                for _ in 0..copies {
+                    let at = self.tick + self.rng.range(0, Self::MAX_DELAY + 1);
+                    self.network.push((at, dest, frame.clone()));
+                }
+            }
+        }
+
+        fn invoke(&mut self) {
+            for process in 0..self.clients.len() {
+                if self.clients[process].is_some() || !self.rng.chance(0.2) {
+                    continue;
+                }
+
+                let key = self.rng.range(0, 3);
+                let value = self.rng.range(0, 5);
+                let mut body = match self.rng.range(0, 3) {
+                    0 => serde_json::json!({"type": "read", "key": key}),
+                    1 => serde_json::json!({"type": "write", "key": key, "value": value}),
+                    _ => serde_json::json!({
+                        "type": "cas",
+                        "key": key,
+                        "from": self.rng.range(0, 5),
+                        "to": value,
+                    }),
+                };
+
+                let node = self.rng.range(0, self.nodes.len() as u64) as usize;
+                let msg_id = self.next_msg_id;
+                self.next_msg_id += 1;
+
+                let serde_json::Value::Object(request) = body.take() else {
+                    unreachable!()
+                };
+                self.events.push(Event {
+                    ts: self.now(),
+                    process: process as u32,
+                    kind: EventKind::Invoke,
+                    node: NodeId::node(node as u32),
+                    body: request.clone(),
+                });
+                self.clients[process] = Some(InFlight {
+                    msg_id,
+                    sent: self.tick,
+                });
+
+                let mut request = request;
+                request.insert("msg_id".to_owned(), msg_id.into());
+                let frame = serde_json::json!({
+                    "src": NodeId::client(process as u32),
+                    "dest": NodeId::node(node as u32),
+                    "body": request,
+                });
+                self.nodes[node].deliver(&serde_json::to_vec(&frame).unwrap());
+                self.send(node);
+            }
+        }
+
+        fn complete(&mut self, client: NodeId, in_reply_to: Option<u32>, frame: &[u8]) {
+            #[derive(Deserialize)]
+            struct Reply {
+                body: crate::history::Body,
+            }
+
+            let process = client.value() as usize;
+            let Some(in_flight) = &self.clients[process] else {
+                return;
+            };
+            if Some(in_flight.msg_id) != in_reply_to {
+                return;
+            }
+
+            let Reply { body } = serde_json::from_slice(frame).unwrap();
+            self.events.push(Event {
+                ts: self.now(),
+                process: process as u32,
+                kind: Event::completion(&body),
+                node: NodeId::node(0),
+                body,
+            });
+            self.clients[process] = None;
+        }
+
+        /// Gives up on requests that were not answered, they may still take effect
+        fn expire(&mut self) {
+            for process in 0..self.clients.len() {
+                let Some(in_flight) = &self.clients[process] else {
+                    continue;
+                };
+                if in_flight.sent + Self::CLIENT_TIMEOUT > self.tick {
+                    continue;
+                }
+
+                self.events.push(Event {
+                    ts: self.now(),
+                    process: process as u32,
+                    kind: EventKind::Info,
+                    node: NodeId::node(0),
+                    body: Default::default(),
+                });
+                self.clients[process] = None;
+            }
+        }
+
+        fn check(&mut self) {
+            for (index, node) in self.nodes.iter().enumerate() {
+                let raft = &node.raft;
+
+                // election safety, at most one leader per term
+                if raft.is_leader() {
+                    let leader = *self.leaders.entry(raft.term).or_insert(raft.node_id);
+                    assert_eq!(leader, raft.node_id, "two leaders in term {}", raft.term);
+                }
+
+                assert!(
+                    raft.commit_index >= self.commits[index],
+                    "{} moved its commit index back from {} to {}",
+                    raft.node_id,
+                    self.commits[index],
+                    raft.commit_index
+                );
+                assert!(raft.last_applied <= raft.commit_index);
+                assert!(raft.commit_index <= raft.log.len());
+                self.commits[index] = raft.commit_index;
+            }
+
+            if self.tick.is_multiple_of(50) {
+                self.check_logs();
+            }
+        }
+
+        /// Log matching, and that committed entries are the same on every node
+        fn check_logs(&self) {
+            let json = |log: &[Entry<_>]| serde_json::to_value(log).unwrap();
+
+            for (i, a) in self.nodes.iter().enumerate() {
+                for b in &self.nodes[i + 1..] {
+                    let (a, b) = (&a.raft, &b.raft);
+                    let common = a.log.len().min(b.log.len());
+
+                    let matching = (1..=common)
+                        .rev()
+                        .find(|&index| a.term_at(index) == b.term_at(index))
+                        .unwrap_or(0);
+                    assert_eq!(
+                        json(&a.log[..matching]),
+                        json(&b.log[..matching]),
+                        "the logs of {} and {} match at {matching} but differ before",
+                        a.node_id,
+                        b.node_id
+                    );
+
+                    let committed = a.commit_index.min(b.commit_index);
+                    assert!(
+                        committed <= matching,
+                        "{} and {} committed different entries at {committed}",
+                        a.node_id,
+                        b.node_id
+                    );
+                }
+            }
+        }
+
+        fn run(mut self, ticks: u64) -> Vec<Event> {
+            for tick in 0..ticks {
+                match tick % 400 {
+                    100 => self.partition(),
+                    300 => self.heal(),
+                    _ => (),
+                }
+                self.step(true);
+            }
+
+            self.heal();
+            while self.clients.iter().any(Option::is_some) {
+                self.step(false);
+            }
+            for _ in 0..100 {
+                self.step(false);
+            }
+            self.check_logs();
+
+            let committed = self.commits.iter().copied().min().unwrap();
+            assert_eq!(
+                self.commits.iter().copied().max().unwrap(),
+                committed,
+                "the nodes did not catch up after the partition healed"
+            );
+
+            self.events
+        }
+    }
+
+    #[test]
+    fn stale_append_does_not_move_commit_index_back() {
+        let mut node = SimNode::new(1, 3);
+
+        node.append(0, 4, 3);
+        assert_eq!(node.raft.commit_index(), 3);
+
+        // sent while the leader thought the node was behind
+        node.append(1, 0, 4);
+        assert_eq!(node.raft.commit_index(), 3);
+
+        node.append(3, 1, 4);
+        assert_eq!(node.raft.commit_index(), 4);
+    }
+
+    #[test]
+    fn elects_a_single_leader() {
+        let mut simulation = Simulation::new(1, 3, 0);
+        while !simulation.nodes.iter().any(|node| node.raft.is_leader()) {
+            assert!(simulation.tick < 1000, "no leader was elected");
+            simulation.step(false);
+        }
+
+        let leader = simulation.nodes.iter().find(|node| node.raft.is_leader());
+        let leader = leader.unwrap().raft.node_id;
+        for _ in 0..100 {
+            simulation.step(false);
+        }
+        for node in &simulation.nodes {
+            assert_eq!(node.raft.leader(), Some(leader));
+        }
+    }
+
+    #[test]
+    fn safe_and_linearizable_under_partitions() {
+        for seed in 0..8 {
+            let events = Simulation::new(seed, 5, 4).run(2000);
+
+            let succeeded = events
+                .iter()
+                .filter(|event| event.kind == EventKind::Ok)
+                .count();
+            assert!(
+                succeeded > 50,
+                "seed {seed}: only {succeeded} requests succeeded"
+            );
+
+            let operations = linearizable::from_history::<u32, u32>(&events).unwrap();
+            if let Err(counterexample) = linearizable::check(&operations) {
+                panic!("seed {seed}: {counterexample}");
+            }
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//! Replays a recorded transcript into a node and compares what it sends
+//!
+//! A [`Node`] is replayed in process, with its messages and timer ticks fed in
+//! exactly the recorded order, so any node that only depends on its inputs
+//! reproduces its recorded outputs exactly. Any other node binary can be
+//! replayed as a child process, with the recorded frames fed to it at their
+//! recorded times, but its timers fire on their own.
+
+use std::{
+    fmt,
+    io::{BufRead, BufReader, Cursor, Write},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+
+use crate::{
+    transcript::{Direction, Entry},
+    Error, MaelstromClient, Node,
+};
+
+/// How long a node binary gets to finish sending after the last recorded entry
+const GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// The index of the expected entry in the transcript, or the number of entries
+    /// if the node sent more than was recorded
+    pub entry: usize,
+    pub expected: Option<Value>,
+    pub actual: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub inputs: usize,
+    pub ticks: usize,
+    pub outputs: usize,
+    pub divergence: Option<Divergence>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "replayed {} frames and {} ticks, the node sent {} frames",
+            self.inputs, self.ticks, self.outputs
+        )?;
+
+        let Some(divergence) = &self.divergence else {
+            return writeln!(f, "no divergence");
+        };
+
+        let show = |frame: &Option<Value>| match frame {
+            Some(frame) => frame.to_string(),
+            None => "nothing".to_owned(),
+        };
+
+        writeln!(
+            f,
+            "first divergence at transcript line {}",
+            divergence.entry + 1
+        )?;
+        writeln!(f, "  expected: {}", show(&divergence.expected))?;
+        writeln!(f, "  actual:   {}", show(&divergence.actual))
+    }
+}
+
+/// Collects everything a replayed node writes
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn parse_frame(line: &[u8]) -> Value {
+    serde_json::from_slice(line)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(line).into_owned()))
+}
+
+/// Compares the frames a node sent with the recorded ones, in order
+fn compare(transcript: &[Entry], actual: Vec<Value>) -> Option<Divergence> {
+    let mut expected = transcript
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.direction == Direction::Out);
+    let mut actual = actual.into_iter();
+
+    loop {
+        match (expected.next(), actual.next()) {
+            (None, None) => return None,
+            (Some((_, entry)), Some(actual)) if entry.frame.as_ref() == Some(&actual) => (),
+            (expected, actual) => {
+                return Some(Divergence {
+                    entry: expected.map_or(transcript.len(), |(index, _)| index),
+                    expected: expected.and_then(|(_, entry)| entry.frame.clone()),
+                    actual,
+                })
+            }
+        }
+    }
+}
+
+/// Replays a transcript into the node returned by `init`, in process
+///
+/// The first recorded frame read by the node must be the init message
+pub fn replay<N: Node>(
+    transcript: &[Entry],
+    init: impl FnOnce(&MaelstromClient) -> N,
+) -> Result<Report, Error> {
+    let first = transcript
+        .iter()
+        .position(|entry| entry.direction == Direction::In)
+        .ok_or(Error::MissingInitMessage)?;
+
+    let capture = Capture::default();
+    let mut init_frame = transcript[first].frame_bytes()?;
+    init_frame.push(b'\n');
+
+    let mut client = MaelstromClient::with_io(Cursor::new(init_frame), capture.clone());
+    client.handle_init()?;
+    let mut node = init(&client);
+
+    let mut report = Report {
+        inputs: 1,
+        ticks: 0,
+        outputs: 0,
+        divergence: None,
+    };
+
+    for entry in &transcript[first + 1..] {
+        match entry.direction {
+            Direction::In => {
+                report.inputs += 1;
+                if let Some(message) = client.receive::<N::Payload>(entry.frame_bytes()?)? {
+                    let (src, msg_id) = (message.src, message.msg_id);
+                    client.catch_panic(src, msg_id, |client| node.handle(client, message))?;
+                }
+            }
+            Direction::Tick => {
+                report.ticks += 1;
+                client.notify_tick()?;
+                node.tick(&mut client)?;
+            }
+            Direction::Out => (),
+        }
+    }
+
+    let output = std::mem::take(&mut *capture.0.lock().unwrap());
+    let actual: Vec<Value> = output
+        .split(|&byte| byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(parse_frame)
+        .collect();
+
+    report.outputs = actual.len();
+    report.divergence = compare(transcript, actual);
+
+    Ok(report)
+}
+
+/// Replays a transcript into a node binary, feeding it frames at their recorded times
+pub fn replay_command(transcript: &[Entry], command: &mut Command) -> Result<Report, Error> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+
+    let reader = std::thread::spawn(move || {
+        stdout
+            .split(b'\n')
+            .map_while(Result::ok)
+            .filter(|line| !line.is_empty())
+            .map(|line| parse_frame(&line))
+            .collect::<Vec<_>>()
+    });
+
+    let mut report = Report {
+        inputs: 0,
+        ticks: 0,
+        outputs: 0,
+        divergence: None,
+    };
+
+    let start = Instant::now();
+    let first_ts = transcript.first().map_or(0, |entry| entry.ts);
+    let offset = |entry: &Entry| Duration::from_micros(entry.ts.saturating_sub(first_ts));
+
+    for entry in transcript {
+        match entry.direction {
+            Direction::In => {
+                std::thread::sleep(
+                    (start + offset(entry)).saturating_duration_since(Instant::now()),
+                );
+
+                let mut frame = entry.frame_bytes()?;
+                frame.push(b'\n');
+                stdin.write_all(&frame)?;
+                stdin.flush()?;
+                report.inputs += 1;
+            }
+            Direction::Tick => report.ticks += 1,
+            Direction::Out => (),
+        }
+    }
+
+    let end = transcript.last().map_or(Duration::ZERO, offset) + GRACE_PERIOD;
+    std::thread::sleep((start + end).saturating_duration_since(Instant::now()));
+
+    drop(stdin);
+    child.wait()?;
+    let actual = reader.join().expect("the stdout reader does not panic");
+
+    report.outputs = actual.len();
+    report.divergence = compare(transcript, actual);
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        interceptor::{Flow, Frame, Interceptor},
+        testing::Wire,
+        NodeId, Response,
+    };
+
+    /// Keeps a running total, and sends it to `n1` on every tick
+    struct Counter {
+        total: u64,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "snake_case", tag = "type")]
+    enum CounterPayload {
+        Add { delta: u64 },
+    }
+
+    impl Node for Counter {
+        type Payload = CounterPayload;
+
+        fn handle(
+            &mut self,
+            client: &mut MaelstromClient,
+            message: crate::Message<Self::Payload>,
+        ) -> Result<(), Error> {
+            let CounterPayload::Add { delta } = message.payload;
+            self.total += delta;
+            client.write(message.response(json!({"type": "add_ok", "total": self.total})))?;
+            Ok(())
+        }
+
+        fn tick(&mut self, client: &mut MaelstromClient) -> Result<(), Error> {
+            client.write_no_response(Response {
+                dest: NodeId::node(1),
+                in_reply_to: None,
+                payload: json!({"type": "total", "total": self.total}),
+            })
+        }
+    }
+
+    /// Records a transcript in memory, like a `TranscriptRecorder`
+    struct Recorder(Arc<Mutex<Vec<Entry>>>);
+
+    impl Recorder {
+        fn record(&mut self, direction: Direction, frame: Option<&Frame>) {
+            let mut entries = self.0.lock().unwrap();
+            let ts = entries.len() as u64;
+            entries.push(Entry {
+                ts,
+                direction,
+                frame: frame.map(|frame| parse_frame(frame.as_bytes())),
+            });
+        }
+    }
+
+    impl Interceptor for Recorder {
+        fn on_read(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+            self.record(Direction::In, Some(frame));
+            Ok(Flow::Continue)
+        }
+
+        fn on_write(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+            self.record(Direction::Out, Some(frame));
+            Ok(Flow::Continue)
+        }
+
+        fn on_tick(&mut self) -> Result<(), Error> {
+            self.record(Direction::Tick, None);
+            Ok(())
+        }
+    }
+
+    /// Runs a counter on `n0`, with a tick after every request
+    fn record() -> Vec<Entry> {
+        let init = json!({
+            "src": "c0",
+            "dest": "n0",
+            "body": {"type": "init", "msg_id": 1, "node_id": "n0", "node_ids": ["n0", "n1"]},
+        });
+        let entries = Arc::new(Mutex::new(Vec::new()));
+
+        let mut client =
+            MaelstromClient::with_io(Cursor::new(format!("{init}\n")), Wire::default());
+        client.add_interceptor(Recorder(entries.clone()));
+        client.handle_init().unwrap();
+        let mut node = Counter { total: 0 };
+
+        for (msg_id, delta) in [(1, 3), (2, 4), (3, 5)] {
+            let add = json!({
+                "src": "c1",
+                "dest": "n0",
+                "body": {"type": "add", "msg_id": msg_id, "delta": delta},
+            });
+            let message = client.receive(add.to_string().into_bytes()).unwrap();
+            node.handle(&mut client, message.unwrap()).unwrap();
+
+            client.notify_tick().unwrap();
+            node.tick(&mut client).unwrap();
+        }
+
+        let entries = entries.lock().unwrap().clone();
+        entries
+    }
+
+    #[test]
+    fn replays_without_divergence() {
+        let transcript = record();
+
+        let report = replay(&transcript, |_| Counter { total: 0 }).unwrap();
+        assert_eq!(
+            report,
+            Report {
+                inputs: 4,
+                ticks: 3,
+                outputs: 7,
+                divergence: None,
+            }
+        );
+        assert!(report.to_string().contains("no divergence"));
+    }
+
+    #[test]
+    fn reports_the_first_divergence() {
+        let transcript = record();
+
+        // a node that starts from another total replies differently to the first add
+        let report = replay(&transcript, |_| Counter { total: 1 }).unwrap();
+        let divergence = report.divergence.unwrap();
+        assert_eq!(divergence.entry, 3);
+        assert_eq!(divergence.expected.unwrap()["body"]["total"], 3);
+        assert_eq!(divergence.actual.unwrap()["body"]["total"], 4);
+
+        // the recorded output was changed
+        let mut changed = transcript.clone();
+        let last = changed.len() - 1;
+        changed[last].frame.as_mut().unwrap()["body"]["total"] = json!(99);
+        let report = replay(&changed, |_| Counter { total: 0 }).unwrap();
+        let divergence = report.divergence.unwrap();
+        assert_eq!(divergence.entry, last);
+        assert_eq!(divergence.actual.unwrap()["body"]["total"], 12);
+
+        // the node sent less than was recorded
+        let mut longer = transcript;
+        longer.push(longer[last].clone());
+        let report = replay(&longer, |_| Counter { total: 0 }).unwrap();
+        let divergence = report.divergence.clone().unwrap();
+        assert_eq!(divergence.entry, last + 1);
+        assert_eq!(divergence.actual, None);
+        assert!(report.to_string().contains("actual:   nothing"));
+    }
+}
@@ -0,0 +1,57 @@
+use std::{collections::HashMap, time::Instant};
+
+use crate::NodeId;
+
+/// Requests sent by a client whose replies the client handles itself,
+/// instead of handing them to the node
+#[derive(Default)]
+pub(crate) struct Requests {
+    pending: HashMap<u32, Pending>,
+}
+
+struct Pending {
+    deadline: Instant,
+    kind: Kind,
+}
+
+pub(crate) enum Kind {
+    /// A request relayed for another node, see [`MaelstromClient::forward`](crate::MaelstromClient::forward)
+    Forward { requester: NodeId, in_reply_to: u32 },
+    /// A request of a call that is waiting for its replies, see [`crate::rpc`]
+    Call,
+    /// A request of a call that already returned, its reply is dropped
+    Late,
+}
+
+impl Requests {
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn insert(&mut self, msg_id: u32, deadline: Instant, kind: Kind) {
+        self.pending.insert(msg_id, Pending { deadline, kind });
+    }
+
+    pub fn take(&mut self, msg_id: u32) -> Option<Kind> {
+        self.pending.remove(&msg_id).map(|pending| pending.kind)
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|pending| pending.deadline).min()
+    }
+
+    /// Removes the requests whose deadline passed
+    pub fn expire(&mut self, now: Instant) -> Vec<Kind> {
+        let expired: Vec<u32> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(&msg_id, _)| msg_id)
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|msg_id| self.take(msg_id))
+            .collect()
+    }
+}
@@ -0,0 +1,201 @@
+//! Sending one request to many nodes and collecting their replies
+//!
+//! [`MaelstromClient::call_all`](crate::MaelstromClient::call_all),
+//! [`call_quorum`](crate::MaelstromClient::call_quorum) and
+//! [`call_any`](crate::MaelstromClient::call_any) block until enough nodes
+//! replied or the timeout passed. Messages for the node that arrive in the
+//! meantime are not lost, they are read afterwards.
+//!
+//! Requests that implement [`Rpc`] can be sent with
+//! [`MaelstromClient::call`](crate::MaelstromClient::call), which returns the
+//! reply as the type that belongs to the request.
+
+use std::fmt;
+
+use serde::{
+    de::{DeserializeOwned, Error as _},
+    Deserialize, Serialize,
+};
+
+use crate::{ErrorCode, ErrorPayload, NodeId};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    /// The node replied with an `error` body
+    Error(ErrorPayload),
+    /// The node did not reply before the timeout
+    Timeout,
+    /// The node replied with a body that is not the expected reply
+    Invalid(String),
+}
+
+impl Failure {
+    /// The error code to pass the failure on with, the ones that are not
+    /// replies from the node are indefinite
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Error(error) => error.code,
+            Self::Timeout => ErrorCode::TIMEOUT,
+            Self::Invalid(_) => ErrorCode::CRASH,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error(error) => write!(f, "{}: {}", error.code, error.text),
+            Self::Timeout => write!(f, "the node did not reply in time"),
+            Self::Invalid(error) => write!(f, "invalid reply: {error}"),
+        }
+    }
+}
+
+/// The replies and failures of a call
+///
+/// Nodes that had not replied when enough others had are in neither
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gathered<R> {
+    pub replies: Vec<(NodeId, R)>,
+    pub failures: Vec<(NodeId, Failure)>,
+}
+
+impl<R> Gathered<R> {
+    pub fn succeeded(&self) -> usize {
+        self.replies.len()
+    }
+
+    /// Whether at least `k` nodes replied successfully
+    pub fn reached(&self, k: usize) -> bool {
+        self.replies.len() >= k
+    }
+}
+
+/// Deserializes the body of a reply, which is a failure if it is an `error` body
+pub(crate) fn parse_reply<R: DeserializeOwned>(
+    mut body: serde_json::Map<String, serde_json::Value>,
+) -> Result<R, Failure> {
+    body.remove("msg_id");
+    body.remove("in_reply_to");
+
+    let is_error = body.get("type").and_then(serde_json::Value::as_str) == Some("error");
+    let body = serde_json::Value::Object(body);
+
+    let reply = if is_error {
+        serde_json::from_value(body).map(Failure::Error).map(Err)
+    } else {
+        serde_json::from_value(body).map(Ok)
+    };
+
+    reply.unwrap_or_else(|err| Err(Failure::Invalid(err.to_string())))
+}
+
+/// A message body with a fixed `type`
+pub trait Body {
+    const TYPE: &'static str;
+}
+
+impl<T: Body + ?Sized> Body for &T {
+    const TYPE: &'static str = T::TYPE;
+}
+
+/// A request whose successful reply is always a [`Rpc::Reply`]
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct Cas { key: u32, from: u32, to: u32 }
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct CasOk {}
+///
+/// impl Body for Cas { const TYPE: &'static str = "cas"; }
+/// impl Body for CasOk { const TYPE: &'static str = "cas_ok"; }
+/// impl Rpc for Cas { type Reply = CasOk; }
+///
+/// let reply: Result<CasOk, Failure> = client.call(dest, &Cas { key, from, to }, timeout)?;
+/// ```
+pub trait Rpc: Body + Serialize {
+    type Reply: Body + Serialize + DeserializeOwned;
+}
+
+/// A body with its `type` field, which is checked when deserializing
+///
+/// Enums of requests can be deserialized as an untagged enum of `Typed` bodies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Typed<T>(pub T);
+
+impl<T: Body + Serialize> Serialize for Typed<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        #[derive(Serialize)]
+        struct Tagged<'a, T> {
+            #[serde(rename = "type")]
+            ty: &'static str,
+            #[serde(flatten)]
+            body: &'a T,
+        }
+
+        Tagged {
+            ty: T::TYPE,
+            body: &self.0,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, T: Body + DeserializeOwned> Deserialize<'de> for Typed<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let mut body = serde_json::Map::deserialize(deserializer)?;
+
+        match body.remove("type") {
+            Some(serde_json::Value::String(ty)) if ty == T::TYPE => (),
+            Some(serde_json::Value::String(ty)) => {
+                return Err(D::Error::unknown_variant(&ty, const { &[T::TYPE] }))
+            }
+            Some(ty) => {
+                return Err(D::Error::custom(format_args!(
+                    "expected a `{}` body, got {ty}",
+                    T::TYPE
+                )))
+            }
+            None => return Err(D::Error::missing_field("type")),
+        }
+
+        serde_json::from_value(serde_json::Value::Object(body))
+            .map(Typed)
+            .map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        kv::rpc::{Read, Write},
+        wire,
+    };
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    #[allow(dead_code)]
+    enum Request {
+        Read(Typed<Read<u32, u32>>),
+        Write(Typed<Write<u32, u32>>),
+    }
+
+    #[test]
+    fn untagged_typed_bodies_report_their_types() {
+        assert_eq!(wire::known_types::<Request>(), ["read", "write"]);
+
+        let write: Request = serde_json::from_str(r#"{"type":"write","key":1,"value":2}"#).unwrap();
+        assert!(matches!(
+            write,
+            Request::Write(Typed(Write { key: 1, value: 2 }))
+        ));
+    }
+}
@@ -0,0 +1,204 @@
+//! Stopping a node and its background tasks cleanly
+//!
+//! A [`Shutdown`] is triggered when stdin is closed, or when the process gets
+//! `SIGINT` or `SIGTERM`. After that [`MaelstromClient::read`](crate::MaelstromClient::read)
+//! stops returning messages, waits for every task started with
+//! [`MaelstromClient::spawn`](crate::MaelstromClient::spawn) to return, flushes
+//! stdout and reports the metrics.
+
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+
+use crate::Error;
+
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    triggered: bool,
+    on_trigger: Vec<Box<dyn FnOnce() + Send>>,
+    tasks: Vec<JoinHandle<Result<(), Error>>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Triggers the shutdown when the process gets `SIGINT` or `SIGTERM`
+    pub(crate) fn on_signals(&self) -> Result<(), Error> {
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+        let shutdown = self.clone();
+
+        std::thread::spawn(move || {
+            if let Some(signal) = signals.forever().next() {
+                eprintln!("Shutting down on signal {signal}");
+                shutdown.trigger();
+            }
+        });
+
+        Ok(())
+    }
+
+    pub fn trigger(&self) {
+        let callbacks = {
+            let mut state = self.inner.state.lock().unwrap();
+            if state.triggered {
+                return;
+            }
+
+            state.triggered = true;
+            std::mem::take(&mut state.on_trigger)
+        };
+
+        self.inner.condvar.notify_all();
+        for callback in callbacks {
+            callback();
+        }
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.inner.state.lock().unwrap().triggered
+    }
+
+    /// Blocks until the shutdown is triggered
+    pub fn wait(&self) {
+        let state = self.inner.state.lock().unwrap();
+        let _state = self
+            .inner
+            .condvar
+            .wait_while(state, |state| !state.triggered)
+            .unwrap();
+    }
+
+    /// Sleeps for `timeout`, or until the shutdown is triggered, returns whether it was
+    ///
+    /// Periodic tasks can loop with `while !shutdown.wait_timeout(interval) { .. }`
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.inner.state.lock().unwrap();
+
+        while !state.triggered {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+
+            state = self
+                .inner
+                .condvar
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+
+        true
+    }
+
+    /// Runs `callback` when the shutdown is triggered, or right away if it already was
+    pub(crate) fn on_trigger(&self, callback: impl FnOnce() + Send + 'static) {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.triggered {
+            drop(state);
+            callback();
+        } else {
+            state.on_trigger.push(Box::new(callback));
+        }
+    }
+
+    pub(crate) fn track(&self, task: JoinHandle<Result<(), Error>>) {
+        self.inner.state.lock().unwrap().tasks.push(task);
+    }
+
+    /// Waits for every tracked task, logging the ones that failed
+    pub(crate) fn join(&self) {
+        let tasks = std::mem::take(&mut self.inner.state.lock().unwrap().tasks);
+
+        for task in tasks {
+            match task.join() {
+                Ok(Ok(())) => (),
+                Ok(Err(err)) => eprintln!("Background task failed: {err}"),
+                Err(_) => eprintln!("Background task panicked"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{flush::FlushPolicy, testing, NodeId, Response};
+
+    #[test]
+    fn tasks_are_joined_and_flushed_when_input_ends() {
+        let (mut client, wire) = testing::client(0, 2, &[]);
+        client.set_flush_policy(FlushPolicy::EndOfHandler).unwrap();
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let task_stopped = stopped.clone();
+        client.spawn(move |mut client, shutdown| {
+            while !shutdown.wait_timeout(Duration::from_millis(1)) {}
+            // detached clients never flush on their own with this policy
+            client.write_no_response(Response {
+                dest: NodeId::node(1),
+                in_reply_to: None,
+                payload: json!({"type": "goodbye"}),
+            })?;
+            task_stopped.store(true, Ordering::SeqCst);
+            Ok(())
+        });
+
+        assert!(client.read::<serde_json::Value>().unwrap().is_none());
+
+        assert!(client.shutdown().is_triggered());
+        assert!(stopped.load(Ordering::SeqCst));
+        let messages = wire.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["body"]["type"], "goodbye");
+    }
+
+    #[test]
+    fn triggering_wakes_every_waiter_once() {
+        let shutdown = Shutdown::new();
+        let calls = Arc::new(Mutex::new(0));
+
+        let counter = calls.clone();
+        shutdown.on_trigger(move || *counter.lock().unwrap() += 1);
+        let waiter = {
+            let shutdown = shutdown.clone();
+            std::thread::spawn(move || shutdown.wait_timeout(Duration::from_secs(60)))
+        };
+
+        assert!(!shutdown.wait_timeout(Duration::from_millis(1)));
+        shutdown.trigger();
+        shutdown.trigger();
+        assert!(waiter.join().unwrap());
+        shutdown.wait();
+
+        // callbacks added afterwards run right away
+        let counter = calls.clone();
+        shutdown.on_trigger(move || *counter.lock().unwrap() += 1);
+        assert_eq!(*calls.lock().unwrap(), 2);
+    }
+}
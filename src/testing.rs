@@ -0,0 +1,128 @@
+//! Clients that read a script and write to memory, for the tests of the crate
+
+use std::{
+    io::{BufRead, Cursor, Read, Write},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+use serde_json::Value;
+
+use crate::MaelstromClient;
+
+/// The stdout of a client
+#[derive(Clone, Default)]
+pub struct Wire(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Wire {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Wire {
+    /// Takes the frames written so far
+    pub fn frames(&self) -> Vec<Vec<u8>> {
+        let bytes = std::mem::take(&mut *self.0.lock().unwrap());
+        bytes
+            .split(|&byte| byte == b'\n')
+            .filter(|frame| !frame.is_empty())
+            .map(<[u8]>::to_vec)
+            .collect()
+    }
+
+    /// Takes the frames written so far, parsed
+    pub fn messages(&self) -> Vec<Value> {
+        self.frames()
+            .iter()
+            .map(|frame| serde_json::from_slice(frame).unwrap())
+            .collect()
+    }
+}
+
+/// The stdin of a [`piped_client`], which stays open until it is dropped
+pub struct Stdin(Sender<Vec<u8>>);
+
+impl Stdin {
+    pub fn send(&self, message: &Value) {
+        self.0.send(format!("{message}\n").into_bytes()).unwrap();
+    }
+}
+
+/// Blocks until the [`Stdin`] sends a line or is dropped
+struct Pipe {
+    lines: Mutex<Receiver<Vec<u8>>>,
+    line: Cursor<Vec<u8>>,
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Pipe {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.line.position() == self.line.get_ref().len() as u64 {
+            if let Ok(line) = self.lines.get_mut().unwrap().recv() {
+                self.line = Cursor::new(line);
+            }
+        }
+        self.line.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.line.consume(amt);
+    }
+}
+
+fn init(id: u32, count: u32) -> Value {
+    let node_ids: Vec<String> = (0..count).map(|i| format!("n{i}")).collect();
+    serde_json::json!({
+        "src": "c0",
+        "dest": format!("n{id}"),
+        "body": {"type": "init", "msg_id": 1, "node_id": format!("n{id}"), "node_ids": node_ids},
+    })
+}
+
+/// Node `n<id>` of a cluster of `count` nodes, which did the init handshake and
+/// then reads `script` from its stdin
+pub fn client(id: u32, count: u32, script: &[Value]) -> (MaelstromClient, Wire) {
+    let mut input = String::new();
+    for line in [&init(id, count)].into_iter().chain(script) {
+        input += &format!("{line}\n");
+    }
+
+    let wire = Wire::default();
+    let mut client = MaelstromClient::with_io(Cursor::new(input.into_bytes()), wire.clone());
+    client.handle_init().unwrap();
+    wire.frames();
+
+    (client, wire)
+}
+
+/// Like [`client`], but the test writes to its stdin while it runs
+pub fn piped_client(id: u32, count: u32) -> (MaelstromClient, Stdin, Wire) {
+    let (stdin, lines) = mpsc::channel();
+    let pipe = Pipe {
+        lines: Mutex::new(lines),
+        line: Cursor::default(),
+    };
+    let input = Cursor::new(format!("{}\n", init(id, count)).into_bytes()).chain(pipe);
+
+    let wire = Wire::default();
+    let mut client = MaelstromClient::with_io(input, wire.clone());
+    client.handle_init().unwrap();
+    wire.frames();
+
+    (client, Stdin(stdin), wire)
+}
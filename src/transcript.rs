@@ -0,0 +1,231 @@
+//! Records every frame a node reads or writes to a jsonl file
+//!
+//! Setting `VORTEX_TRANSCRIPT_DIR` makes every [`MaelstromClient`](crate::MaelstromClient)
+//! record its transcript, including the init handshake, to `<dir>/<node id>.jsonl`.
+//! Each line looks like
+//!
+//! ```text
+//! {"ts":1700000000000000,"direction":"in","frame":{"src":"c1","dest":"n1","body":{...}}}
+//! ```
+//!
+//! where `ts` is in microseconds since the unix epoch. Frames are recorded as
+//! the node sees them, so outgoing frames are recorded even if a fault injector
+//! drops them later on. Timer ticks of a [`Node`](crate::Node) are recorded as
+//! entries with the `tick` direction and no frame.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, LineWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{de::IgnoredAny, Deserialize, Serialize};
+
+use crate::{
+    interceptor::{Flow, Frame, Interceptor},
+    Error, NodeId,
+};
+
+pub const ENV: &str = "VORTEX_TRANSCRIPT_DIR";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    In,
+    Out,
+    Tick,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub ts: u64,
+    pub direction: Direction,
+    /// A string if the recorded frame was not valid json, and `None` for ticks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame: Option<serde_json::Value>,
+}
+
+impl Entry {
+    /// The frame as it was read or written, without the trailing newline
+    ///
+    /// The keys of json frames come back sorted, so only frames that had sorted
+    /// keys come back byte for byte
+    pub fn frame_bytes(&self) -> Result<Vec<u8>, Error> {
+        match &self.frame {
+            Some(serde_json::Value::String(frame)) => Ok(frame.clone().into_bytes()),
+            Some(frame) => Ok(serde_json::to_vec(frame)?),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Reads a transcript written by [`TranscriptRecorder`]
+pub fn read(path: impl AsRef<Path>) -> Result<Vec<Entry>, Error> {
+    let file = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+
+    for line in file.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            entries.push(serde_json::from_str(&line)?);
+        }
+    }
+
+    Ok(entries)
+}
+
+pub struct TranscriptRecorder {
+    dir: PathBuf,
+    /// Opened once the node id is known, from the first frame
+    file: Option<LineWriter<File>>,
+}
+
+impl TranscriptRecorder {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            file: None,
+        }
+    }
+
+    /// Records to the directory in `VORTEX_TRANSCRIPT_DIR`, if it is set
+    pub fn from_env() -> Option<Self> {
+        std::env::var_os(ENV).map(Self::new)
+    }
+
+    fn record(&mut self, direction: Direction, frame: Option<&Frame>) -> Result<(), Error> {
+        #[derive(Deserialize)]
+        struct Route {
+            src: NodeId,
+            dest: NodeId,
+        }
+
+        let file = match (&mut self.file, frame) {
+            (Some(file), _) => file,
+            // ticks before the first frame have nowhere to go
+            (None, None) => return Ok(()),
+            (None, Some(frame)) => {
+                let Ok(route) = serde_json::from_slice::<Route>(frame.as_bytes()) else {
+                    return Ok(());
+                };
+                let node_id = match direction {
+                    Direction::In => route.dest,
+                    Direction::Out | Direction::Tick => route.src,
+                };
+
+                let path = self.dir.join(format!("{node_id}.jsonl"));
+
+                std::fs::create_dir_all(&self.dir)?;
+                self.file.insert(LineWriter::new(File::create(path)?))
+            }
+        };
+
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_micros() as u64);
+
+        let mut line = format!(
+            r#"{{"ts":{ts},"direction":{}"#,
+            serde_json::to_string(&direction)?
+        )
+        .into_bytes();
+
+        if let Some(frame) = frame {
+            line.extend_from_slice(br#","frame":"#);
+
+            // frames that are not valid json are still recorded, as a string
+            if serde_json::from_slice::<IgnoredAny>(frame.as_bytes()).is_ok() {
+                line.extend_from_slice(frame.as_bytes());
+            } else {
+                let frame = String::from_utf8_lossy(frame.as_bytes());
+                serde_json::to_writer(&mut line, &frame)?;
+            }
+        }
+
+        line.extend_from_slice(b"}\n");
+        file.write_all(&line)?;
+
+        Ok(())
+    }
+}
+
+impl Interceptor for TranscriptRecorder {
+    fn on_read(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+        self.record(Direction::In, Some(frame))?;
+        Ok(Flow::Continue)
+    }
+
+    fn on_write(&mut self, frame: &mut Frame) -> Result<Flow, Error> {
+        self.record(Direction::Out, Some(frame))?;
+        Ok(Flow::Continue)
+    }
+
+    fn on_tick(&mut self) -> Result<(), Error> {
+        self.record(Direction::Tick, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as u64
+    }
+
+    #[test]
+    fn round_trips_through_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut recorder = TranscriptRecorder::new(dir.path().join("transcripts"));
+
+        let frames: [&[u8]; 4] = [
+            br#"{"body":{"msg_id":1,"type":"init"},"dest":"n1","src":"c1"}"#,
+            br#"{"body":{"in_reply_to":1,"type":"init_ok"},"dest":"c1","src":"n1"}"#,
+            br#"{"body":{"type":"gossip"},"dest":"n2","src":"n1"}"#,
+            b"not json",
+        ];
+
+        // ticks before the node id is known are not recorded
+        recorder.on_tick().unwrap();
+        let start = now();
+        let mut frame = Frame::new(frames[0].to_vec());
+        assert_eq!(recorder.on_read(&mut frame).unwrap(), Flow::Continue);
+        for frame in &frames[1..3] {
+            let mut frame = Frame::new(frame.to_vec());
+            assert_eq!(recorder.on_write(&mut frame).unwrap(), Flow::Continue);
+        }
+        recorder.on_tick().unwrap();
+        let mut frame = Frame::new(frames[3].to_vec());
+        assert_eq!(recorder.on_read(&mut frame).unwrap(), Flow::Continue);
+        let end = now();
+
+        let entries = read(dir.path().join("transcripts/n1.jsonl")).unwrap();
+        let directions: Vec<Direction> = entries.iter().map(|entry| entry.direction).collect();
+        assert_eq!(
+            directions,
+            [
+                Direction::In,
+                Direction::Out,
+                Direction::Out,
+                Direction::Tick,
+                Direction::In
+            ]
+        );
+
+        assert!(entries.windows(2).all(|pair| pair[0].ts <= pair[1].ts));
+        assert!(start <= entries[0].ts && entries[4].ts <= end);
+
+        let bytes: Vec<Vec<u8>> = entries
+            .iter()
+            .map(|entry| entry.frame_bytes().unwrap())
+            .collect();
+        assert_eq!(bytes[..3], frames[..3]);
+        assert_eq!(entries[3].frame, None);
+        assert_eq!(bytes[4], frames[3]);
+        assert_eq!(entries[4].frame, Some(serde_json::Value::from("not json")));
+    }
+}
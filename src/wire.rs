@@ -0,0 +1,520 @@
+//! Encoding and decoding frames without buffering the body
+//!
+//! A body has the `msg_id` and `in_reply_to` of the envelope next to the
+//! fields of the payload. With `#[serde(flatten)]` serde buffers the whole body
+//! before the payload sees it, which is most of the cost of a small message.
+//! Instead, messages are decoded in one pass that takes out the envelope
+//! fields on the way, so payloads can still borrow from the frame, and written
+//! with an [`Encoder`] that caches the envelope prefix of each destination.
+//!
+//! This only saves the buffer around the payload. Internally tagged payloads,
+//! like most of the ones in this crate, still buffer their own fields in serde's
+//! `Content` until they have seen the `type`.
+
+use std::{borrow::Cow, collections::HashMap, fmt, marker::PhantomData};
+
+use serde::{
+    de::{self, DeserializeSeed, IgnoredAny, IntoDeserializer, MapAccess, Visitor},
+    forward_to_deserialize_any, Deserialize, Deserializer, Serialize,
+};
+
+use crate::{Message, NodeId};
+
+/// The envelope of a frame, without deserializing the payload
+#[derive(Debug, Deserialize)]
+pub struct Header<'a> {
+    pub src: NodeId,
+    pub dest: NodeId,
+    #[serde(borrow)]
+    pub body: BodyHeader<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BodyHeader<'a> {
+    #[serde(rename = "type", borrow)]
+    pub ty: Option<Cow<'a, str>>,
+    pub msg_id: Option<u32>,
+    pub in_reply_to: Option<u32>,
+}
+
+pub fn header(frame: &[u8]) -> Result<Header<'_>, serde_json::Error> {
+    serde_json::from_slice(frame)
+}
+
+pub(crate) fn deserialize_message<'de, D, T>(deserializer: D) -> Result<Message<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    const FIELDS: &[&str] = &["src", "dest", "body"];
+
+    deserializer.deserialize_struct("Message", FIELDS, MessageVisitor(PhantomData))
+}
+
+struct MessageVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for MessageVisitor<T> {
+    type Value = Message<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a maelstrom message")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let (mut src, mut dest, mut body) = (None, None, None);
+
+        while let Some(key) = map.next_key::<Key>()? {
+            match key.0.as_ref() {
+                "src" => src = Some(map.next_value()?),
+                "dest" => dest = Some(map.next_value()?),
+                "body" => body = Some(map.next_value_seed(BodySeed(PhantomData))?),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        let (payload, envelope) = body.ok_or_else(|| de::Error::missing_field("body"))?;
+
+        Ok(Message {
+            src: src.ok_or_else(|| de::Error::missing_field("src"))?,
+            dest: dest.ok_or_else(|| de::Error::missing_field("dest"))?,
+            msg_id: envelope.msg_id,
+            in_reply_to: envelope.in_reply_to,
+            payload,
+        })
+    }
+}
+
+/// A map key, borrowed from the frame unless it has escapes
+struct Key<'de>(Cow<'de, str>);
+
+impl<'de> Deserialize<'de> for Key<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeyVisitor;
+
+        impl<'de> Visitor<'de> for KeyVisitor {
+            type Value = Key<'de>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                write!(formatter, "a field name")
+            }
+
+            fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<Self::Value, E> {
+                Ok(Key(Cow::Borrowed(v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(Key(Cow::Owned(v.to_owned())))
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+                Ok(Key(Cow::Owned(v)))
+            }
+        }
+
+        deserializer.deserialize_str(KeyVisitor)
+    }
+}
+
+#[derive(Default)]
+struct Envelope {
+    msg_id: Option<u32>,
+    in_reply_to: Option<u32>,
+}
+
+struct BodySeed<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> DeserializeSeed<'de> for BodySeed<T> {
+    type Value = (T, Envelope);
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        let mut envelope = Envelope::default();
+        let payload = T::deserialize(Body {
+            inner: deserializer,
+            envelope: &mut envelope,
+        })?;
+
+        Ok((payload, envelope))
+    }
+}
+
+/// A body that hides `msg_id` and `in_reply_to` from the payload and records them instead
+struct Body<'a, D> {
+    inner: D,
+    envelope: &'a mut Envelope,
+}
+
+impl<'de, D: Deserializer<'de>> Deserializer<'de> for Body<'_, D> {
+    type Error = D::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_map(BodyVisitor {
+            inner: visitor,
+            envelope: self.envelope,
+        })
+    }
+
+    /// Payloads without fields, like `()`, still have to record the envelope
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_map(BodyVisitor {
+            inner: UnitVisitor(visitor),
+            envelope: self.envelope,
+        })
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option newtype_struct seq tuple tuple_struct map struct
+        enum identifier
+    }
+}
+
+/// Skips the fields of a body and visits a unit
+struct UnitVisitor<V>(V);
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for UnitVisitor<V> {
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a message body")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+        self.0.visit_unit()
+    }
+}
+
+struct BodyVisitor<'a, V> {
+    inner: V,
+    envelope: &'a mut Envelope,
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for BodyVisitor<'_, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a message body")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        self.inner.visit_map(BodyAccess {
+            inner: map,
+            envelope: self.envelope,
+        })
+    }
+}
+
+struct BodyAccess<'a, A> {
+    inner: A,
+    envelope: &'a mut Envelope,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for BodyAccess<'_, A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        while let Some(Key(key)) = self.inner.next_key()? {
+            match key {
+                Cow::Borrowed("msg_id") => self.envelope.msg_id = self.inner.next_value()?,
+                Cow::Borrowed("in_reply_to") => {
+                    self.envelope.in_reply_to = self.inner.next_value()?
+                }
+                Cow::Owned(key) if key == "msg_id" || key == "in_reply_to" => {
+                    let id = self.inner.next_value()?;
+                    match key.as_str() {
+                        "msg_id" => self.envelope.msg_id = id,
+                        _ => self.envelope.in_reply_to = id,
+                    }
+                }
+                Cow::Borrowed(key) => {
+                    return seed
+                        .deserialize(de::value::BorrowedStrDeserializer::new(key))
+                        .map(Some)
+                }
+                Cow::Owned(key) => return seed.deserialize(key.into_deserializer()).map(Some),
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<S::Value, Self::Error> {
+        self.inner.next_value_seed(seed)
+    }
+}
+
+/// The `type`s a payload accepts, found by deserializing it from a body with a
+/// `type` that no payload has
+///
+/// Tagged enums report the variants they expected for it, which also works
+/// through untagged enums of them, like [`crate::raft::Payload`]. Those report
+/// only that nothing matched, so the variants are collected on the side.
+pub(crate) fn known_types<'de, T: Deserialize<'de>>() -> Vec<&'static str> {
+    EXPECTED.with_borrow_mut(Vec::clear);
+    let _ = T::deserialize(Probe);
+    EXPECTED.with_borrow_mut(std::mem::take)
+}
+
+thread_local! {
+    static EXPECTED: std::cell::RefCell<Vec<&'static str>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// A body with nothing but a `type` that no payload has
+struct Probe;
+
+impl Probe {
+    const TYPE: &'static str = "\0probe";
+}
+
+impl<'de> Deserializer<'de> for Probe {
+    type Error = ProbeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(de::value::MapDeserializer::new(std::iter::once((
+            "type",
+            Self::TYPE,
+        ))))
+    }
+
+    /// Externally tagged enums
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        variants: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        Err(de::Error::unknown_variant(Self::TYPE, variants))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[derive(Debug)]
+struct ProbeError;
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "not a body")
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+impl de::Error for ProbeError {
+    fn custom<T: fmt::Display>(_: T) -> Self {
+        Self
+    }
+
+    fn unknown_variant(_: &str, expected: &'static [&'static str]) -> Self {
+        EXPECTED.with_borrow_mut(|types| types.extend(expected));
+        Self
+    }
+}
+
+/// Writes frames, with the envelope up to the payload cached for each destination
+#[derive(Debug, Default)]
+pub struct Encoder {
+    src: Option<NodeId>,
+    /// `{"src":"n1","dest":"n2","body":{"msg_id":` by destination
+    prefixes: HashMap<NodeId, Box<[u8]>>,
+    payload: Vec<u8>,
+}
+
+impl Encoder {
+    /// Clients are not limited, so this keeps a node that talks to many of them from growing forever
+    const MAX_PREFIXES: usize = 1024;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a frame to `out`, which is the same as serializing the envelope
+    /// with `serde_json`, with a `null` for missing ids
+    ///
+    /// The payload has to serialize to a map, like every message body
+    pub fn encode<T: Serialize + ?Sized>(
+        &mut self,
+        out: &mut Vec<u8>,
+        src: NodeId,
+        dest: NodeId,
+        msg_id: Option<u32>,
+        in_reply_to: Option<u32>,
+        payload: &T,
+    ) -> Result<(), serde_json::Error> {
+        self.payload.clear();
+        serde_json::to_writer(&mut self.payload, payload)?;
+        let Some(fields) = self.payload.strip_prefix(b"{") else {
+            return Err(serde::ser::Error::custom("the payload must be a map"));
+        };
+
+        if self.src != Some(src) || self.prefixes.len() >= Self::MAX_PREFIXES {
+            self.src = Some(src);
+            self.prefixes.clear();
+        }
+
+        let prefix = match self.prefixes.get(&dest) {
+            Some(prefix) => prefix,
+            None => {
+                let mut prefix = Vec::new();
+                prefix.extend_from_slice(b"{\"src\":");
+                serde_json::to_writer(&mut prefix, &src)?;
+                prefix.extend_from_slice(b",\"dest\":");
+                serde_json::to_writer(&mut prefix, &dest)?;
+                prefix.extend_from_slice(b",\"body\":{\"msg_id\":");
+                self.prefixes.entry(dest).or_insert(prefix.into())
+            }
+        };
+
+        out.extend_from_slice(prefix);
+        write_id(out, msg_id);
+        out.extend_from_slice(b",\"in_reply_to\":");
+        write_id(out, in_reply_to);
+        if fields != b"}" {
+            out.push(b',');
+        }
+        out.extend_from_slice(fields);
+        out.push(b'}');
+
+        Ok(())
+    }
+}
+
+fn write_id(out: &mut Vec<u8>, id: Option<u32>) {
+    match id {
+        Some(id) => out.extend_from_slice(itoa::Buffer::new().format(id).as_bytes()),
+        None => out.extend_from_slice(b"null"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Reference<'a, P> {
+        src: NodeId,
+        dest: NodeId,
+        body: ReferenceBody<'a, P>,
+    }
+
+    #[derive(Serialize)]
+    struct ReferenceBody<'a, P> {
+        msg_id: Option<u32>,
+        in_reply_to: Option<u32>,
+        #[serde(flatten)]
+        payload: &'a P,
+    }
+
+    /// Encodes a frame and checks that it is what `serde_json` writes for it
+    fn assert_encodes<P: Serialize>(
+        encoder: &mut Encoder,
+        (src, dest): (NodeId, NodeId),
+        (msg_id, in_reply_to): (Option<u32>, Option<u32>),
+        payload: &P,
+    ) {
+        let mut out = Vec::new();
+        encoder
+            .encode(&mut out, src, dest, msg_id, in_reply_to, payload)
+            .unwrap();
+
+        let reference = serde_json::to_vec(&Reference {
+            src,
+            dest,
+            body: ReferenceBody {
+                msg_id,
+                in_reply_to,
+                payload,
+            },
+        })
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(reference).unwrap()
+        );
+    }
+
+    #[test]
+    fn encodes_like_serde_json() {
+        let mut encoder = Encoder::new();
+        let nodes = (NodeId::node(1), NodeId::node(2));
+        let payload = json!({"type": "echo", "echo": "hello"});
+
+        for ids in [
+            (None, None),
+            (Some(1), None),
+            (None, Some(2)),
+            (Some(3), Some(4)),
+        ] {
+            assert_encodes(&mut encoder, nodes, ids, &payload);
+        }
+        assert_encodes(&mut encoder, nodes, (Some(5), None), &json!({}));
+        assert_encodes(
+            &mut encoder,
+            (NodeId::node(1), NodeId::client(3)),
+            (Some(6), Some(7)),
+            &json!({"type": "read_ok", "quo\"te\\": "line\nbreak", "ünï": [1, {"a\tb": null}]}),
+        );
+    }
+
+    #[test]
+    fn payloads_must_be_maps() {
+        let mut out = Vec::new();
+        let result = Encoder::new().encode(
+            &mut out,
+            NodeId::node(1),
+            NodeId::node(2),
+            None,
+            None,
+            &[1, 2],
+        );
+
+        assert!(result.is_err());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn prefixes_are_evicted() {
+        let mut encoder = Encoder::new();
+        let payload = json!({"type": "echo_ok"});
+
+        for client in 0..Encoder::MAX_PREFIXES as u32 + 10 {
+            let nodes = (NodeId::node(1), NodeId::client(client));
+            assert_encodes(&mut encoder, nodes, (Some(client), Some(1)), &payload);
+            assert!(encoder.prefixes.len() <= Encoder::MAX_PREFIXES);
+        }
+        assert_eq!(encoder.prefixes.len(), 10);
+
+        // a destination that was evicted, and one that was cached for another source
+        let evicted = (NodeId::node(1), NodeId::client(0));
+        assert_encodes(&mut encoder, evicted, (Some(1), None), &payload);
+        let other_source = (NodeId::node(2), NodeId::client(0));
+        assert_encodes(&mut encoder, other_source, (Some(1), None), &payload);
+        assert_eq!(encoder.prefixes.len(), 1);
+    }
+}
@@ -0,0 +1,76 @@
+//! The node implementations behind the `vortex` binary
+//!
+//! Each workload is a type that reads its parameters from a [`Config`] and then
+//! serves one node on stdin and stdout. `vortex <workload> [--param value ...]`
+//! looks the workload up in [`REGISTRY`] by name, so adding a workload means
+//! implementing [`Workload`] and adding an [`Entry`] for it.
+//!
+//! Every workload also takes `--flush`, see [`crate::flush`].
+
+use crate::{config::Config, Error, FlushPolicy, MaelstromClient};
+
+pub mod broadcast;
+pub mod echo;
+pub mod g_counter;
+pub mod lin_kv;
+pub mod unique_ids;
+
+pub trait Workload: Sized {
+    /// The subcommand that runs this workload
+    const NAME: &'static str;
+    /// A one line description for the usage message
+    const ABOUT: &'static str;
+
+    /// Reads the parameters, this runs before the init handshake
+    fn new(config: &Config) -> Result<Self, Error>;
+
+    /// Serves messages until stdin is closed
+    fn run(self, client: MaelstromClient) -> Result<(), Error>;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    pub name: &'static str,
+    pub about: &'static str,
+    run: fn(&Config) -> Result<(), Error>,
+}
+
+impl Entry {
+    pub const fn of<W: Workload>() -> Self {
+        Self {
+            name: W::NAME,
+            about: W::ABOUT,
+            run: run::<W>,
+        }
+    }
+
+    /// Reads the parameters, rejects unknown ones, and then runs the workload
+    pub fn run(&self, config: &Config) -> Result<(), Error> {
+        (self.run)(config)
+    }
+}
+
+fn run<W: Workload>(config: &Config) -> Result<(), Error> {
+    let workload = W::new(config)?;
+    let flush = config.get("flush", FlushPolicy::Immediate)?;
+    config.finish()?;
+
+    let mut client = MaelstromClient::new()?;
+    client.set_flush_policy(flush)?;
+
+    workload.run(client)
+}
+
+pub const REGISTRY: &[Entry] = &[
+    Entry::of::<echo::Echo>(),
+    Entry::of::<unique_ids::UniqueIds>(),
+    Entry::of::<broadcast::Broadcast>(),
+    Entry::of::<broadcast::SingleNode>(),
+    Entry::of::<broadcast::Unacknowledged>(),
+    Entry::of::<g_counter::GCounter>(),
+    Entry::of::<lin_kv::LinKv>(),
+];
+
+pub fn find(name: &str) -> Option<&'static Entry> {
+    REGISTRY.iter().find(|entry| entry.name == name)
+}
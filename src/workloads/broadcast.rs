@@ -0,0 +1,372 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use super::Workload;
+use crate::{config::Config, rpc::Typed, Error, MaelstromClient, NodeId, Response};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum BroadcastPayload {
+    Broadcast(rpc::Broadcast),
+    Read(rpc::Read),
+    Topology(rpc::Topology),
+    Gossip(rpc::Gossip),
+    GossipResponse(rpc::GossipResponse),
+}
+
+mod rpc {
+    use std::collections::{HashMap, HashSet};
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        rpc::{Body, Rpc},
+        NodeId,
+    };
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Broadcast {
+        pub message: u32,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct BroadcastOk {}
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Read {}
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ReadOk {
+        pub messages: HashSet<u32>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Topology {
+        pub topology: HashMap<NodeId, Vec<NodeId>>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct TopologyOk {}
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Gossip {
+        /// Only set if the gossip should be acknowledged
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub gossip_id: Option<u32>,
+        pub values: Vec<u32>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct GossipResponse {
+        pub gossip_id: u32,
+    }
+
+    impl Body for Broadcast {
+        const TYPE: &'static str = "broadcast";
+    }
+
+    impl Body for BroadcastOk {
+        const TYPE: &'static str = "broadcast_ok";
+    }
+
+    impl Body for Read {
+        const TYPE: &'static str = "read";
+    }
+
+    impl Body for ReadOk {
+        const TYPE: &'static str = "read_ok";
+    }
+
+    impl Body for Topology {
+        const TYPE: &'static str = "topology";
+    }
+
+    impl Body for TopologyOk {
+        const TYPE: &'static str = "topology_ok";
+    }
+
+    impl Body for Gossip {
+        const TYPE: &'static str = "gossip";
+    }
+
+    impl Body for GossipResponse {
+        const TYPE: &'static str = "gossip_response";
+    }
+
+    impl Rpc for Broadcast {
+        type Reply = BroadcastOk;
+    }
+
+    impl Rpc for Read {
+        type Reply = ReadOk;
+    }
+
+    impl Rpc for Topology {
+        type Reply = TopologyOk;
+    }
+
+    /// Only acknowledged gossip gets a reply
+    impl Rpc for Gossip {
+        type Reply = GossipResponse;
+    }
+}
+
+/// Every node keeps all messages it has seen, and gossips the ones each
+/// neighbor does not know about yet every interval
+///
+/// Acknowledged gossip is resent until the neighbor replies, so it survives
+/// partitions. The neighbors are the ones from the topology message, or with
+/// `--fanout N`, the next `N` nodes on a ring of all nodes.
+pub struct Broadcast(Gossip);
+
+/// Like [`Broadcast`], but gossip is never acknowledged, so messages that are
+/// dropped by the network are lost
+pub struct Unacknowledged(Gossip);
+
+/// Keeps every message on the node that received it, which only works with a single node
+pub struct SingleNode;
+
+impl Workload for Broadcast {
+    const NAME: &'static str = "broadcast";
+    const ABOUT: &'static str =
+        "gossip that is resent until acknowledged [--fanout N] [--gossip-interval MS]";
+
+    fn new(config: &Config) -> Result<Self, Error> {
+        Gossip::new(config, true).map(Self)
+    }
+
+    fn run(self, client: MaelstromClient) -> Result<(), Error> {
+        self.0.run(client)
+    }
+}
+
+impl Workload for Unacknowledged {
+    const NAME: &'static str = "broadcast-unacked";
+    const ABOUT: &'static str = "gossip that is sent once [--fanout N] [--gossip-interval MS]";
+
+    fn new(config: &Config) -> Result<Self, Error> {
+        Gossip::new(config, false).map(Self)
+    }
+
+    fn run(self, client: MaelstromClient) -> Result<(), Error> {
+        self.0.run(client)
+    }
+}
+
+impl Workload for SingleNode {
+    const NAME: &'static str = "broadcast-single";
+    const ABOUT: &'static str = "broadcast on a single node";
+
+    fn new(_: &Config) -> Result<Self, Error> {
+        Ok(Self)
+    }
+
+    fn run(self, mut client: MaelstromClient) -> Result<(), Error> {
+        let mut values = HashSet::new();
+
+        while let Some(message) = client.read::<BroadcastPayload>()? {
+            match &message.payload {
+                BroadcastPayload::Broadcast(broadcast) => {
+                    values.insert(broadcast.message);
+                    client.write(message.reply(broadcast, rpc::BroadcastOk {}))?;
+                }
+                BroadcastPayload::Read(read) => {
+                    let messages = values.clone();
+                    client.write(message.reply(read, rpc::ReadOk { messages }))?;
+                }
+                BroadcastPayload::Topology(topology) => {
+                    client.write(message.reply(topology, rpc::TopologyOk {}))?;
+                }
+                BroadcastPayload::Gossip(_) | BroadcastPayload::GossipResponse(_) => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct Gossip {
+    interval: Duration,
+    fanout: Option<usize>,
+    acknowledged: bool,
+}
+
+#[derive(Default)]
+struct State {
+    values: HashSet<u32>,
+    known: HashMap<NodeId, HashSet<u32>>,
+    neighbors: Vec<NodeId>,
+    /// The unacknowledged gossip to each neighbor by gossip id
+    gossip: HashMap<NodeId, BTreeMap<u32, Vec<u32>>>,
+}
+
+/// Recovers the state from a handler that panicked while holding the lock, the
+/// handlers run under [`MaelstromClient::catch_panic`] and the node keeps going
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Gossip {
+    fn new(config: &Config, acknowledged: bool) -> Result<Self, Error> {
+        Ok(Self {
+            interval: config.duration("gossip-interval", Duration::from_millis(100))?,
+            fanout: config.optional("fanout")?,
+            acknowledged,
+        })
+    }
+
+    fn run(self, mut client: MaelstromClient) -> Result<(), Error> {
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let Self {
+            interval,
+            fanout,
+            acknowledged,
+        } = self;
+
+        let gossip_state = state.clone();
+        client.spawn(move |mut client, shutdown| {
+            let mut gossip_id = 0;
+
+            while !shutdown.wait_timeout(interval) {
+                let state = &mut *lock(&gossip_state);
+
+                for &n in &state.neighbors {
+                    let known = state.known.entry(n).or_default();
+
+                    let values: Vec<u32> = state.values.difference(known).copied().collect();
+
+                    if values.is_empty() {
+                        continue;
+                    }
+
+                    let gossip_id = if acknowledged {
+                        gossip_id += 1;
+
+                        state
+                            .gossip
+                            .entry(n)
+                            .or_default()
+                            .insert(gossip_id, values.clone());
+
+                        Some(gossip_id)
+                    } else {
+                        known.extend(&values);
+                        None
+                    };
+
+                    client.write_no_response(Response {
+                        dest: n,
+                        in_reply_to: None,
+                        payload: Typed(rpc::Gossip { gossip_id, values }),
+                    })?;
+                }
+
+                client.flush()?;
+            }
+
+            Ok(())
+        });
+
+        while let Some(message) = client.read::<BroadcastPayload>()? {
+            let (src, msg_id) = (message.src, message.msg_id);
+            client.catch_panic(src, msg_id, |client| {
+                match &message.payload {
+                    BroadcastPayload::Broadcast(broadcast) => {
+                        lock(&state).values.insert(broadcast.message);
+                        client.write(message.reply(broadcast, rpc::BroadcastOk {}))?;
+                    }
+                    BroadcastPayload::Read(read) => {
+                        let messages = lock(&state).values.clone();
+                        client.write(message.reply(read, rpc::ReadOk { messages }))?;
+                    }
+                    BroadcastPayload::Topology(topology) => {
+                        let neighbors = match fanout {
+                            Some(fanout) => client.cluster().successors(fanout).collect(),
+                            None => topology
+                                .topology
+                                .get(&client.node_id())
+                                .cloned()
+                                .unwrap_or_default(),
+                        };
+
+                        lock(&state).neighbors = neighbors;
+                        client.write(message.reply(topology, rpc::TopologyOk {}))?;
+                    }
+                    BroadcastPayload::Gossip(gossip) => {
+                        {
+                            let state = &mut *lock(&state);
+
+                            state.known.entry(src).or_default().extend(&gossip.values);
+                            state.values.extend(&gossip.values);
+                        }
+
+                        if let Some(gossip_id) = gossip.gossip_id {
+                            client
+                                .write(message.reply(gossip, rpc::GossipResponse { gossip_id }))?;
+                        }
+                    }
+                    &BroadcastPayload::GossipResponse(rpc::GossipResponse { gossip_id }) => {
+                        let state = &mut *lock(&state);
+
+                        let Some(gossip) = state.gossip.get_mut(&src) else {
+                            return Ok(());
+                        };
+
+                        let Some(values) = gossip.remove(&gossip_id) else {
+                            return Ok(());
+                        };
+                        *gossip = gossip.split_off(&gossip_id);
+
+                        let known = state.known.entry(src).or_default();
+                        known.extend(&values);
+                        state.values.extend(values);
+                    }
+                }
+
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rpc::Body, wire};
+
+    #[test]
+    fn payload_tags_are_the_body_types() {
+        assert_eq!(
+            wire::known_types::<BroadcastPayload>(),
+            [
+                rpc::Broadcast::TYPE,
+                rpc::Read::TYPE,
+                rpc::Topology::TYPE,
+                rpc::Gossip::TYPE,
+                rpc::GossipResponse::TYPE,
+            ]
+        );
+    }
+
+    #[test]
+    fn a_panic_while_locked_does_not_lose_the_state() {
+        let state = Mutex::new(State::default());
+
+        let _ = std::panic::catch_unwind(|| {
+            let mut state = lock(&state);
+            state.values.insert(1);
+            panic!("the handler panicked");
+        });
+
+        assert!(state.is_poisoned());
+        assert_eq!(lock(&state).values, HashSet::from([1]));
+    }
+}
@@ -0,0 +1,39 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use super::Workload;
+use crate::{config::Config, Error, MaelstromClient};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum EchoPayload<'a> {
+    Echo { echo: Cow<'a, str> },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum EchoResponse<'a> {
+    EchoOk { echo: &'a str },
+}
+
+pub struct Echo;
+
+impl Workload for Echo {
+    const NAME: &'static str = "echo";
+    const ABOUT: &'static str = "replies to every echo with the same text";
+
+    fn new(_: &Config) -> Result<Self, Error> {
+        Ok(Self)
+    }
+
+    fn run(self, mut client: MaelstromClient) -> Result<(), Error> {
+        while let Some(message) = client.read::<EchoPayload>()? {
+            let EchoPayload::Echo { echo } = &message.payload;
+
+            client.write(message.response(EchoResponse::EchoOk { echo }))?;
+        }
+
+        Ok(())
+    }
+}
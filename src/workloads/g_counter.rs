@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use super::Workload;
+use crate::{
+    config::Config,
+    kv::{self, rpc::Cas, CasError},
+    rpc::Failure,
+    Error, ErrorCode, MaelstromClient, NodeId,
+};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum GrowPayload {
+    Add(rpc::Add),
+    Read(rpc::Read),
+}
+
+mod rpc {
+    use serde::{Deserialize, Serialize};
+
+    use crate::rpc::{Body, Rpc};
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Add {
+        pub delta: u32,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct AddOk {}
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Read {}
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ReadOk {
+        pub value: u32,
+    }
+
+    impl Body for Add {
+        const TYPE: &'static str = "add";
+    }
+
+    impl Body for AddOk {
+        const TYPE: &'static str = "add_ok";
+    }
+
+    impl Body for Read {
+        const TYPE: &'static str = "read";
+    }
+
+    impl Body for ReadOk {
+        const TYPE: &'static str = "read_ok";
+    }
+
+    impl Rpc for Add {
+        type Reply = AddOk;
+    }
+
+    impl Rpc for Read {
+        type Reply = ReadOk;
+    }
+}
+
+const COUNTER: &str = "counter";
+
+/// A counter in `seq-kv`, updated with compare and swap
+pub struct GCounter {
+    timeout: Duration,
+}
+
+impl Workload for GCounter {
+    const NAME: &'static str = "g-counter";
+    const ABOUT: &'static str = "a grow-only counter stored in seq-kv";
+
+    fn new(config: &Config) -> Result<Self, Error> {
+        Ok(Self {
+            timeout: config.duration("kv-timeout", Duration::from_millis(500))?,
+        })
+    }
+
+    fn run(self, mut client: MaelstromClient) -> Result<(), Error> {
+        let timeout = self.timeout;
+        let mut current_value = 0;
+
+        while let Some(message) = client.read::<GrowPayload>()? {
+            let (src, msg_id) = (message.src, message.msg_id);
+            client.catch_panic(src, msg_id, |client| {
+                match &message.payload {
+                    GrowPayload::Add(add) => loop {
+                        let cas = Cas {
+                            key: COUNTER,
+                            from: current_value,
+                            to: current_value + add.delta,
+                            create_if_not_exists: true,
+                        };
+
+                        match client.call(NodeId::seq_kv(), &cas, timeout)? {
+                            Ok(_) => {
+                                current_value += add.delta;
+                                client.write(message.reply(add, rpc::AddOk {}))?;
+                                break;
+                            }
+                            Err(Failure::Error(error))
+                                if error.code == ErrorCode::PRECONDITION_FAILED =>
+                            {
+                                let Ok(CasError { actual }) =
+                                    serde_json::from_value(error.text.clone().into())
+                                else {
+                                    client.write(message.error_response(error.code, error.text))?;
+                                    break;
+                                };
+
+                                current_value = actual;
+                            }
+                            Err(failure) => {
+                                client.write(
+                                    message.error_response(failure.code(), failure.to_string()),
+                                )?;
+                                break;
+                            }
+                        }
+                    },
+                    GrowPayload::Read(request) => {
+                        let read = kv::rpc::Read::<_, u32>::new(COUNTER);
+                        match client.call(NodeId::seq_kv(), &read, timeout)? {
+                            Ok(kv::rpc::ReadOk { value }) => {
+                                current_value = value;
+                                client.write(message.reply(request, rpc::ReadOk { value }))?;
+                            }
+                            Err(Failure::Error(error))
+                                if error.code == ErrorCode::KEY_DOES_NOT_EXIST =>
+                            {
+                                client.write(message.reply(request, rpc::ReadOk { value: 0 }))?;
+                            }
+                            Err(failure) => {
+                                client.write(
+                                    message.error_response(failure.code(), failure.to_string()),
+                                )?;
+                            }
+                        }
+                    }
+                }
+
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rpc::Body, wire};
+
+    #[test]
+    fn payload_tags_are_the_body_types() {
+        assert_eq!(
+            wire::known_types::<GrowPayload>(),
+            [rpc::Add::TYPE, rpc::Read::TYPE]
+        );
+    }
+}
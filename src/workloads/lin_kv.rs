@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::Workload;
+use crate::{
+    config::Config,
+    raft::{self, StateMachine},
+    Error, ErrorCode, ErrorPayload, MaelstromClient,
+};
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub(crate) enum KvPayload {
+    Read {
+        key: u32,
+    },
+    Write {
+        key: u32,
+        value: u32,
+    },
+    Cas {
+        key: u32,
+        from: u32,
+        to: u32,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+#[allow(clippy::enum_variant_names)]
+pub(crate) enum KvResponse {
+    ReadOk { value: u32 },
+    WriteOk,
+    CasOk,
+}
+
+#[derive(Default)]
+pub(crate) struct Kv {
+    values: HashMap<u32, u32>,
+}
+
+impl StateMachine for Kv {
+    type Request = KvPayload;
+    type Response = KvResponse;
+
+    fn apply(&mut self, request: &KvPayload) -> Result<KvResponse, ErrorPayload> {
+        match *request {
+            KvPayload::Read { key } => match self.values.get(&key) {
+                Some(&value) => Ok(KvResponse::ReadOk { value }),
+                None => Err(ErrorPayload::new(
+                    ErrorCode::KEY_DOES_NOT_EXIST,
+                    "key does not exist",
+                )),
+            },
+            KvPayload::Write { key, value } => {
+                self.values.insert(key, value);
+                Ok(KvResponse::WriteOk)
+            }
+            KvPayload::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => match self.values.get_mut(&key) {
+                Some(value) if *value == from => {
+                    *value = to;
+                    Ok(KvResponse::CasOk)
+                }
+                Some(value) => Err(ErrorPayload::new(
+                    ErrorCode::PRECONDITION_FAILED,
+                    format!("current value {value} is not {from}"),
+                )),
+                None if create_if_not_exists => {
+                    self.values.insert(key, to);
+                    Ok(KvResponse::CasOk)
+                }
+                None => Err(ErrorPayload::new(
+                    ErrorCode::KEY_DOES_NOT_EXIST,
+                    "key does not exist",
+                )),
+            },
+        }
+    }
+}
+
+/// A key value store replicated with [`raft`]
+pub struct LinKv;
+
+impl Workload for LinKv {
+    const NAME: &'static str = "lin-kv";
+    const ABOUT: &'static str = "a linearizable key value store replicated with raft";
+
+    fn new(_: &Config) -> Result<Self, Error> {
+        Ok(Self)
+    }
+
+    fn run(self, client: MaelstromClient) -> Result<(), Error> {
+        raft::run(client, Kv::default())
+    }
+}
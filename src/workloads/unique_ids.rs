@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+
+use super::Workload;
+use crate::{config::Config, Error, MaelstromClient};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum GeneratePayload {
+    Generate,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum GenerateResponse<T> {
+    GenerateOk { id: T },
+}
+
+/// Ids are the node id and the message id of the reply, which are unique together
+pub struct UniqueIds;
+
+impl Workload for UniqueIds {
+    const NAME: &'static str = "unique-ids";
+    const ABOUT: &'static str = "generates globally unique ids without coordination";
+
+    fn new(_: &Config) -> Result<Self, Error> {
+        Ok(Self)
+    }
+
+    fn run(self, mut client: MaelstromClient) -> Result<(), Error> {
+        while let Some(message) = client.read::<GeneratePayload>()? {
+            client.write(message.response(GenerateResponse::GenerateOk {
+                id: [client.node_id().value(), client.message_id().unwrap()],
+            }))?;
+        }
+
+        Ok(())
+    }
+}